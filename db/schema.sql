@@ -6,15 +6,88 @@ CREATE TABLE IF NOT EXISTS devices (
   id TEXT PRIMARY KEY,
   did_document TEXT NOT NULL,
   public_key TEXT NOT NULL,
-  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+  -- Block timestamp and provenance of the registering input (never wall-clock time)
+  created_at TIMESTAMP NOT NULL,
+  input_index INTEGER NOT NULL,
+  msg_sender TEXT NOT NULL,
+  -- Address that registered the device; may run lifecycle actions, grant access and delete it
+  owner TEXT NOT NULL,
+  -- Reading schema the device's submissions are validated against; NULL = opaque data
+  device_type TEXT,
+  -- JSON array of lowercase addresses allowed to submit for this device; NULL = any sender
+  authorized_senders TEXT,
+  -- Lifecycle state ('active' | 'deactivated') and replay nonce for signed lifecycle actions
+  status TEXT NOT NULL DEFAULT 'active',
+  lifecycle_nonce INTEGER NOT NULL DEFAULT 0
 );
 
+-- History of every public key a device has used; the current key has valid_until_input NULL
+CREATE TABLE IF NOT EXISTS device_keys (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  device_id TEXT NOT NULL,
+  public_key TEXT NOT NULL,
+  valid_from_input INTEGER NOT NULL,
+  valid_until_input INTEGER,
+  created_at TIMESTAMP NOT NULL,
+  FOREIGN KEY (device_id) REFERENCES devices(id)
+);
+
+-- Reading schemas per device type (see src/schema.rs for the definition format)
+CREATE TABLE IF NOT EXISTS device_schemas (
+  device_type TEXT PRIMARY KEY,
+  schema TEXT NOT NULL,
+  version INTEGER NOT NULL DEFAULT 1,
+  registered_by TEXT NOT NULL,
+  input_index INTEGER NOT NULL
+);
+
+-- Built-in device types available from genesis
+INSERT OR IGNORE INTO device_schemas (device_type, schema, registered_by, input_index) VALUES
+  ('temperature', '{"fields":{"temperature":{"type":"number","min":-273.15},"humidity":{"type":"number","required":false,"min":0,"max":100}}}', 'genesis', 0),
+  ('gps', '{"fields":{"latitude":{"type":"number","min":-90,"max":90},"longitude":{"type":"number","min":-180,"max":180},"altitude":{"type":"number","required":false}}}', 'genesis', 0),
+  ('energy_meter', '{"fields":{"energy_kwh":{"type":"number","min":0},"power_w":{"type":"number","required":false,"min":0},"voltage":{"type":"number","required":false,"min":0}}}', 'genesis', 0);
+
 -- Per-device message counter for deterministic nonce generation
 CREATE TABLE IF NOT EXISTS device_counters (
   device_id TEXT PRIMARY KEY,
   counter   INTEGER NOT NULL
 );
 
+-- Addresses granted access to a device's data by its owner or an admin, or
+-- by purchase. Purchased grants are bounded by message counter or expiry;
+-- both NULL means unlimited.
+CREATE TABLE IF NOT EXISTS access_grants (
+  device_id TEXT NOT NULL,
+  grantee TEXT NOT NULL,
+  granted_by TEXT NOT NULL,
+  input_index INTEGER NOT NULL,
+  min_counter INTEGER,             -- covers readings with min_counter < counter ...
+  max_counter INTEGER,             -- ... and counter <= max_counter
+  starts_at INTEGER,               -- covers readings submitted from (Unix seconds) ...
+  expires_at INTEGER,              -- ... and before (Unix seconds)
+  purchased INTEGER NOT NULL DEFAULT 0, -- bought with purchase_access; the owner cannot revoke it
+  PRIMARY KEY (device_id, grantee, purchased),
+  FOREIGN KEY (device_id) REFERENCES devices(id)
+);
+
+-- Price an owner asks for a device's data, per reading or per subscription period
+CREATE TABLE IF NOT EXISTS access_prices (
+  device_id TEXT PRIMARY KEY,
+  asset TEXT NOT NULL,             -- 'ether' or ERC-20 token address
+  price TEXT NOT NULL,             -- uint128 decimal string
+  period_seconds INTEGER,          -- NULL: price per reading
+  set_by TEXT NOT NULL,
+  input_index INTEGER NOT NULL,
+  FOREIGN KEY (device_id) REFERENCES devices(id)
+);
+
+-- Keys that sign an address's inspect queries for device data, registered by that address
+CREATE TABLE IF NOT EXISTS reader_keys (
+  address TEXT PRIMARY KEY,
+  public_key TEXT NOT NULL,        -- public JWK
+  input_index INTEGER NOT NULL
+);
+
 -- Table to store encrypted IoT sensor data payloads from devices
 CREATE TABLE IF NOT EXISTS sensor_data (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -24,7 +97,12 @@ CREATE TABLE IF NOT EXISTS sensor_data (
   stage2_key_hash TEXT NOT NULL,
   counter INTEGER NOT NULL,
   timestamp TIMESTAMP NOT NULL,
-  FOREIGN KEY (device_id) REFERENCES devices(id)
+  input_index INTEGER NOT NULL,
+  msg_sender TEXT NOT NULL,
+  -- Device key that was current when the reading was accepted
+  key_id INTEGER,
+  FOREIGN KEY (device_id) REFERENCES devices(id),
+  FOREIGN KEY (key_id) REFERENCES device_keys(id)
 );
 
 -- Table to cache results of analytics and other computations
@@ -34,5 +112,127 @@ CREATE TABLE IF NOT EXISTS analytics (
   metric_type TEXT NOT NULL,
   value REAL NOT NULL,
   time_window TEXT NOT NULL,
-  calculated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+  calculated_at TIMESTAMP NOT NULL,
+  UNIQUE (device_id, metric_type, time_window)
+);
+
+-- Welford accumulators behind the published analytics, per device, metric and window
+CREATE TABLE IF NOT EXISTS analytics_windows (
+  device_id TEXT NOT NULL,
+  metric TEXT NOT NULL,
+  time_window TEXT NOT NULL,
+  count INTEGER NOT NULL,
+  mean REAL NOT NULL,
+  m2 REAL NOT NULL,
+  min REAL NOT NULL,
+  max REAL NOT NULL,
+  PRIMARY KEY (device_id, metric, time_window)
+);
+
+-- Threshold rules on a single device or on every device of a type
+CREATE TABLE IF NOT EXISTS rules (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  device_id TEXT,
+  device_type TEXT,
+  field TEXT NOT NULL,
+  comparator TEXT NOT NULL,
+  threshold REAL NOT NULL,
+  hysteresis REAL NOT NULL DEFAULT 0,
+  slash_amount TEXT,               -- stake slashed on each trigger (uint128 decimal)
+  created_by TEXT NOT NULL,
+  input_index INTEGER NOT NULL,
+  CHECK ((device_id IS NULL) <> (device_type IS NULL))
+);
+
+-- Whether a rule is currently in alert for a device (hysteresis state)
+CREATE TABLE IF NOT EXISTS rule_states (
+  rule_id INTEGER NOT NULL,
+  device_id TEXT NOT NULL,
+  active INTEGER NOT NULL,
+  PRIMARY KEY (rule_id, device_id),
+  FOREIGN KEY (rule_id) REFERENCES rules(id)
+);
+
+-- Alert transitions ('triggered' | 'cleared') raised by rules
+CREATE TABLE IF NOT EXISTS alerts (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  rule_id INTEGER NOT NULL,
+  device_id TEXT NOT NULL,
+  field TEXT NOT NULL,
+  value REAL NOT NULL,
+  threshold REAL NOT NULL,
+  state TEXT NOT NULL,
+  input_index INTEGER NOT NULL,
+  timestamp TIMESTAMP NOT NULL,
+  FOREIGN KEY (rule_id) REFERENCES rules(id)
+);
+
+-- Accepted, authenticated readings counted towards data-contribution rewards
+CREATE TABLE IF NOT EXISTS reward_progress (
+  device_id TEXT PRIMARY KEY,
+  readings INTEGER NOT NULL
+);
+
+-- Vouchers emitted by the dApp, kept for audit
+CREATE TABLE IF NOT EXISTS vouchers (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  destination TEXT NOT NULL,
+  payload BLOB NOT NULL,
+  reason TEXT NOT NULL,
+  input_index INTEGER NOT NULL
+);
+
+-- Internal ledger; amounts are uint128 decimal strings
+CREATE TABLE IF NOT EXISTS balances (
+  address TEXT NOT NULL,
+  asset TEXT NOT NULL,             -- 'ether' or ERC-20 token address
+  amount TEXT NOT NULL,
+  PRIMARY KEY (address, asset)
+);
+
+-- Portal deposits credited to the ledger
+CREATE TABLE IF NOT EXISTS deposits (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  asset TEXT NOT NULL,
+  depositor TEXT NOT NULL,
+  amount TEXT NOT NULL,
+  input_index INTEGER NOT NULL
+);
+
+-- Portal deposits the ledger cannot hold (above 128 bits, or overflowing the
+-- depositor's balance), kept so the funds are not lost
+CREATE TABLE IF NOT EXISTS unclaimed_deposits (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  asset TEXT NOT NULL,
+  depositor TEXT NOT NULL,
+  amount TEXT NOT NULL,            -- uint256 decimal
+  input_index INTEGER NOT NULL
+);
+
+-- Stakes locked by device owners at registration; amount is what is left after slashing
+CREATE TABLE IF NOT EXISTS stakes (
+  device_id TEXT PRIMARY KEY,
+  owner TEXT NOT NULL,
+  asset TEXT NOT NULL,
+  amount TEXT NOT NULL,
+  input_index INTEGER NOT NULL,
+  unbonding_until INTEGER          -- set when the device is deleted; releasable from then (Unix seconds)
+);
+
+-- Slashed stake moved to the treasury
+CREATE TABLE IF NOT EXISTS slashes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  device_id TEXT NOT NULL,
+  asset TEXT NOT NULL,
+  amount TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  slashed_by TEXT NOT NULL,
+  input_index INTEGER NOT NULL
+);
+
+-- The dApp's own address, learned from the DAppAddressRelay
+CREATE TABLE IF NOT EXISTS dapp_address (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  address TEXT NOT NULL,
+  input_index INTEGER NOT NULL
 );
@@ -0,0 +1,137 @@
+// lcore-node/src/abi.rs
+//
+// Minimal Solidity ABI encoding for notices and vouchers
+
+use crate::error::LCoreError;
+
+/// A value to ABI-encode
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Address([u8; 20]),
+    Uint(u128),
+    Int(i128),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl Token {
+    /// Parse a `0x`-prefixed 20-byte hex address
+    pub fn address(address: &str) -> Result<Self, LCoreError> {
+        let bytes = hex::decode(address.trim_start_matches("0x"))?;
+        let bytes: [u8; 20] = bytes
+            .try_into()
+            .map_err(|_| LCoreError::InvalidInput(format!("Invalid address: {}", address)))?;
+        Ok(Token::Address(bytes))
+    }
+
+    fn is_dynamic(&self) -> bool {
+        matches!(self, Token::String(_) | Token::Bytes(_))
+    }
+}
+
+/// Encode `tokens` as the arguments of a function call or as a tuple, i.e.
+/// what `abi.encode(...)` produces and `abi.decode(data, (...))` accepts.
+pub fn encode(tokens: &[Token]) -> Vec<u8> {
+    let head_len = tokens.len() * 32;
+    let mut head = Vec::with_capacity(head_len);
+    let mut tail = Vec::new();
+
+    for token in tokens {
+        if token.is_dynamic() {
+            head.extend_from_slice(&uint_word((head_len + tail.len()) as u128));
+            tail.extend(encode_dynamic(token));
+        } else {
+            head.extend_from_slice(&encode_static(token));
+        }
+    }
+
+    head.extend(tail);
+    head
+}
+
+/// Calldata for a call: 4-byte selector followed by the encoded arguments
+pub fn encode_call(selector: [u8; 4], tokens: &[Token]) -> Vec<u8> {
+    let mut calldata = selector.to_vec();
+    calldata.extend(encode(tokens));
+    calldata
+}
+
+fn encode_static(token: &Token) -> [u8; 32] {
+    match token {
+        Token::Address(address) => {
+            let mut word = [0u8; 32];
+            word[12..].copy_from_slice(address);
+            word
+        }
+        Token::Uint(value) => uint_word(*value),
+        Token::Int(value) => {
+            // Two's complement, sign-extended to 256 bits
+            let mut word = if *value < 0 { [0xffu8; 32] } else { [0u8; 32] };
+            word[16..].copy_from_slice(&value.to_be_bytes());
+            word
+        }
+        Token::Bool(value) => uint_word(u128::from(*value)),
+        Token::String(_) | Token::Bytes(_) => unreachable!("dynamic tokens are encoded in the tail"),
+    }
+}
+
+fn encode_dynamic(token: &Token) -> Vec<u8> {
+    let bytes = match token {
+        Token::String(value) => value.as_bytes(),
+        Token::Bytes(value) => value.as_slice(),
+        _ => unreachable!("static tokens are encoded in the head"),
+    };
+    let mut out = uint_word(bytes.len() as u128).to_vec();
+    out.extend_from_slice(bytes);
+    out.resize(32 + bytes.len().div_ceil(32) * 32, 0);
+    out
+}
+
+fn uint_word(value: u128) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_static() {
+        let encoded = encode(&[Token::Uint(1), Token::Int(-1), Token::Bool(true)]);
+        assert_eq!(
+            hex::encode(encoded),
+            concat!(
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+            )
+        );
+    }
+
+    #[test]
+    fn test_encode_dynamic() {
+        // abi.encode("dave", uint256(1))
+        let encoded = encode(&[Token::String("dave".to_string()), Token::Uint(1)]);
+        assert_eq!(
+            hex::encode(encoded),
+            concat!(
+                "0000000000000000000000000000000000000000000000000000000000000040",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "0000000000000000000000000000000000000000000000000000000000000004",
+                "6461766500000000000000000000000000000000000000000000000000000000",
+            )
+        );
+    }
+
+    #[test]
+    fn test_encode_transfer_call() {
+        let recipient = Token::address("0x70997970c51812dc3a010c7d01b50e0d17dc79c8").unwrap();
+        let calldata = encode_call([0xa9, 0x05, 0x9c, 0xbb], &[recipient, Token::Uint(1_000)]);
+        assert_eq!(calldata.len(), 4 + 64);
+        assert_eq!(&calldata[16..36], hex::decode("70997970c51812dc3a010c7d01b50e0d17dc79c8").unwrap().as_slice());
+        assert!(Token::address("0x1234").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+// lcore-node/src/access.rs
+//
+// Paid access to a device's data stream
+
+use crate::database::{Database, DeviceRow, SensorDataRow};
+use crate::error::LCoreError;
+use crate::portals::Asset;
+use crate::rollup::AdvanceMetadata;
+
+/// Largest counter, timestamp or period SQLite can store as an INTEGER
+pub const MAX_STORED: u64 = i64::MAX as u64;
+
+/// Price an owner asks for a device's data, paid from the internal ledger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPrice {
+    pub device_id: String,
+    pub asset: Asset,
+    /// Cost of one unit: one reading, or one period when `period_seconds` is set
+    pub price: u128,
+    pub period_seconds: Option<u64>,
+}
+
+/// A consumer's right to read a device's data. Grants given by the owner are
+/// unbounded and revocable. Purchased grants are kept apart from them and
+/// cover a range of message counters (per-reading pricing) or of block times
+/// (subscriptions); neither reaches back before the purchase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessGrant {
+    pub device_id: String,
+    pub grantee: String,
+    /// Counter of the device's last reading when the range was bought; not covered
+    pub min_counter: Option<u64>,
+    pub max_counter: Option<u64>,
+    /// Block time the subscription was bought at; readings from then on are covered
+    pub starts_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    /// Bought with `purchase_access`; owners cannot revoke or overwrite it
+    pub purchased: bool,
+}
+
+impl AccessGrant {
+    pub fn is_unbounded(&self) -> bool {
+        self.max_counter.is_none() && self.expires_at.is_none()
+    }
+
+    /// Whether the grant covers a stored reading. Only the reading itself is
+    /// consulted, so inspect calls decide this without a clock.
+    pub fn covers(&self, reading: &SensorDataRow) -> Result<bool, LCoreError> {
+        let in_range = self
+            .max_counter
+            .is_some_and(|max| self.min_counter.unwrap_or(0) < reading.counter && reading.counter <= max);
+        if self.is_unbounded() || in_range {
+            return Ok(true);
+        }
+        match self.expires_at {
+            Some(expires_at) => {
+                let timestamp = reading_timestamp(reading)?;
+                Ok(self.starts_at.unwrap_or(0) <= timestamp && timestamp < expires_at)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Block time of a stored reading in Unix seconds
+fn reading_timestamp(reading: &SensorDataRow) -> Result<u64, LCoreError> {
+    chrono::DateTime::parse_from_rfc3339(&reading.timestamp)
+        .ok()
+        .and_then(|timestamp| u64::try_from(timestamp.timestamp()).ok())
+        .ok_or_else(|| LCoreError::Internal(format!("Invalid reading timestamp: {}", reading.timestamp)))
+}
+
+/// Buy `quantity` readings or periods of access to `device` for the input's
+/// sender. The cost moves from the buyer's balance to the owner's and the
+/// buyer's grant is created or extended. Returns the cost, or why the
+/// purchase is refused.
+pub fn purchase(
+    db: &Database,
+    device: &DeviceRow,
+    quantity: u64,
+    metadata: &AdvanceMetadata,
+) -> Result<Result<u128, String>, LCoreError> {
+    let buyer = metadata.msg_sender.as_str();
+    let price = match db.get_access_price(&device.id)? {
+        Some(price) => price,
+        None => return Ok(Err(format!("Device {} is not for sale", device.id))),
+    };
+    if quantity == 0 {
+        return Ok(Err("Quantity must be positive".to_string()));
+    }
+    let cost = match price.price.checked_mul(u128::from(quantity)) {
+        Some(cost) => cost,
+        None => return Ok(Err("Purchase cost overflows".to_string())),
+    };
+
+    if db.get_access_grants(&device.id, buyer)?.iter().any(AccessGrant::is_unbounded) {
+        return Ok(Err(format!("{} already has unlimited access to {}", buyer, device.id)));
+    }
+    let mut grant = db.get_purchased_grant(&device.id, buyer)?.unwrap_or(AccessGrant {
+        device_id: device.id.clone(),
+        grantee: buyer.to_string(),
+        min_counter: None,
+        max_counter: None,
+        starts_at: None,
+        expires_at: None,
+        purchased: true,
+    });
+    match price.period_seconds {
+        None => {
+            // The next `quantity` readings the device submits. A range the device has not
+            // submitted past yet is extended; otherwise a new one starts after its latest reading.
+            let current = db.get_message_counter(&device.id)?;
+            let from = match grant.max_counter {
+                Some(max) if grant.min_counter.is_some() && max >= current => max,
+                _ => {
+                    grant.min_counter = Some(current);
+                    current
+                }
+            };
+            grant.max_counter = Some(from.saturating_add(quantity).min(MAX_STORED));
+        }
+        Some(period) => {
+            // Periods from now on. A subscription still running is extended; otherwise a new one starts now.
+            let from = match grant.expires_at {
+                Some(expires_at) if grant.starts_at.is_some() && expires_at >= metadata.timestamp => expires_at,
+                _ => {
+                    grant.starts_at = Some(metadata.timestamp);
+                    metadata.timestamp
+                }
+            };
+            grant.expires_at = Some(from.saturating_add(period.saturating_mul(quantity)).min(MAX_STORED));
+        }
+    }
+
+    let tx = db.savepoint()?;
+    if !db.debit_balance(buyer, &price.asset, cost)? {
+        return Ok(Err(format!("Insufficient {} balance for {}", price.asset.as_str(), cost)));
+    }
+    db.credit_balance(&device.owner, &price.asset, cost)?;
+    db.upsert_access_grant(&grant, metadata)?;
+    tx.commit()?;
+    Ok(Ok(cost))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+    const BUYER: &str = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+
+    fn store_reading(db: &Database, device_id: &str, metadata: &AdvanceMetadata) -> SensorDataRow {
+        let counter = db.next_message_counter(device_id).unwrap();
+        db.insert_sensor_data(device_id, b"ciphertext", "k1", "k2", counter, metadata).unwrap();
+        db.get_latest_sensor_data(device_id).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_purchase_per_reading() {
+        let db = Database::open(":memory:").unwrap();
+        let owner = AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200);
+        db.insert_device("did:example:paid", "{}", "{}", None, &[], &owner).unwrap();
+        let device = db.get_device("did:example:paid").unwrap().unwrap();
+        store_reading(&db, &device.id, &owner);
+
+        let buyer = AdvanceMetadata::for_test(BUYER, 2, 1_704_067_300);
+        assert!(purchase(&db, &device, 2, &buyer).unwrap().is_err());
+
+        db.set_access_price(&AccessPrice {
+            device_id: device.id.clone(),
+            asset: Asset::Ether,
+            price: 10,
+            period_seconds: None,
+        }, &owner).unwrap();
+        assert!(purchase(&db, &device, 2, &buyer).unwrap().is_err());
+
+        db.credit_balance(BUYER, &Asset::Ether, 25).unwrap();
+        assert_eq!(purchase(&db, &device, 2, &buyer).unwrap(), Ok(20));
+        assert_eq!(db.get_balance(BUYER, &Asset::Ether).unwrap(), 5);
+        assert_eq!(db.get_balance(OWNER, &Asset::Ether).unwrap(), 20);
+
+        // Readings 2 and 3 are covered, the one already stored and reading 4 are not
+        let grant = db.get_purchased_grant(&device.id, BUYER).unwrap().unwrap();
+        let first = db.get_latest_sensor_data(&device.id).unwrap().unwrap();
+        assert_eq!(first.counter, 1);
+        assert!(!grant.covers(&first).unwrap());
+        let mut covered = Vec::new();
+        for input_index in 3..6 {
+            let reading = store_reading(&db, &device.id, &AdvanceMetadata::for_test(OWNER, input_index, 1_704_067_400));
+            covered.push(grant.covers(&reading).unwrap());
+        }
+        assert_eq!(covered, vec![true, true, false]);
+
+        // Once those are submitted, a new purchase covers only readings still to come
+        db.credit_balance(BUYER, &Asset::Ether, 5).unwrap();
+        purchase(&db, &device, 1, &AdvanceMetadata::for_test(BUYER, 6, 1_704_067_500)).unwrap().unwrap();
+        let grant = db.get_purchased_grant(&device.id, BUYER).unwrap().unwrap();
+        assert_eq!((grant.min_counter, grant.max_counter), (Some(4), Some(5)));
+        let latest = db.get_latest_sensor_data(&device.id).unwrap().unwrap();
+        assert!(!grant.covers(&latest).unwrap());
+    }
+
+    #[test]
+    fn test_purchase_subscription() {
+        let db = Database::open(":memory:").unwrap();
+        let owner = AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200);
+        db.insert_device("did:example:sub", "{}", "{}", None, &[], &owner).unwrap();
+        let device = db.get_device("did:example:sub").unwrap().unwrap();
+        db.set_access_price(&AccessPrice {
+            device_id: device.id.clone(),
+            asset: Asset::Ether,
+            price: 100,
+            period_seconds: Some(3_600),
+        }, &owner).unwrap();
+        db.credit_balance(BUYER, &Asset::Ether, 300).unwrap();
+        let before = store_reading(&db, &device.id, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_100));
+
+        assert_eq!(purchase(&db, &device, 1, &AdvanceMetadata::for_test(BUYER, 2, 1_704_067_200)).unwrap(), Ok(100));
+        let grant = db.get_purchased_grant(&device.id, BUYER).unwrap().unwrap();
+        assert_eq!((grant.starts_at, grant.expires_at), (Some(1_704_067_200), Some(1_704_070_800)));
+        // History from before the purchase stays locked
+        assert!(!grant.covers(&before).unwrap());
+
+        let inside = store_reading(&db, &device.id, &AdvanceMetadata::for_test(OWNER, 3, 1_704_070_799));
+        assert!(grant.covers(&inside).unwrap());
+        let after = store_reading(&db, &device.id, &AdvanceMetadata::for_test(OWNER, 4, 1_704_070_800));
+        assert!(!grant.covers(&after).unwrap());
+
+        // Renewing extends from the current expiry
+        purchase(&db, &device, 1, &AdvanceMetadata::for_test(BUYER, 5, 1_704_068_000)).unwrap().unwrap();
+        let grant = db.get_purchased_grant(&device.id, BUYER).unwrap().unwrap();
+        assert_eq!((grant.starts_at, grant.expires_at), (Some(1_704_067_200), Some(1_704_074_400)));
+
+        // After it lapses, a new subscription starts at the new purchase
+        purchase(&db, &device, 1, &AdvanceMetadata::for_test(BUYER, 6, 1_704_080_000)).unwrap().unwrap();
+        let grant = db.get_purchased_grant(&device.id, BUYER).unwrap().unwrap();
+        assert_eq!((grant.starts_at, grant.expires_at), (Some(1_704_080_000), Some(1_704_083_600)));
+        assert!(!grant.covers(&inside).unwrap());
+    }
+}
@@ -0,0 +1,250 @@
+// lcore-node/src/analytics.rs
+//
+// Rolling aggregates over accepted readings, computed inside the VM
+
+use crate::abi::{self, Token};
+use crate::database::Database;
+use crate::error::LCoreError;
+use crate::rollup::AdvanceMetadata;
+use serde_json::Value;
+use tracing::warn;
+
+/// Aggregation windows as (label, length in seconds), aligned to the Unix epoch.
+pub const WINDOWS: [(&str, u64); 2] = [("1h", 3_600), ("1d", 86_400)];
+
+/// Fixed-point decimals of the value in published analytics notices.
+pub const NOTICE_VALUE_DECIMALS: u32 = 6;
+
+/// Running count/mean/variance/min/max using Welford's algorithm, so a window
+/// can be updated one reading at a time without keeping the readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunningStats {
+    pub count: u64,
+    pub mean: f64,
+    pub m2: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl RunningStats {
+    /// Stats of a single observation
+    pub fn new(value: f64) -> Self {
+        Self { count: 1, mean: value, m2: 0.0, min: value, max: value }
+    }
+
+    /// Fold one more observation into the stats
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Whether every aggregate is still a finite number; extreme readings can
+    /// overflow the mean or `m2` even when each reading is finite
+    pub fn is_finite(&self) -> bool {
+        [self.mean, self.m2, self.min, self.max].iter().all(|v| v.is_finite())
+    }
+
+    /// Population variance of the observations
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+
+    /// Published statistics as (suffix, value) pairs
+    pub fn published(&self) -> [(&'static str, f64); 5] {
+        [
+            ("count", self.count as f64),
+            ("min", self.min),
+            ("max", self.max),
+            ("mean", self.mean),
+            ("variance", self.variance()),
+        ]
+    }
+}
+
+/// Top-level numeric fields of a JSON object reading, in key order.
+/// Readings that are not JSON objects carry no analytics.
+pub fn numeric_fields(data: &[u8]) -> Vec<(String, f64)> {
+    match serde_json::from_slice::<Value>(data) {
+        Ok(Value::Object(object)) => {
+            let mut fields: Vec<(String, f64)> = object
+                .into_iter()
+                .filter_map(|(key, value)| value.as_f64().filter(|v| v.is_finite()).map(|v| (key, v)))
+                .collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            fields
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Label of the window of length `size` containing `timestamp`, e.g.
+/// `1h:2024-01-01T10:00:00+00:00`.
+pub fn window_label(label: &str, size: u64, timestamp: u64) -> Result<String, LCoreError> {
+    let start = timestamp - timestamp % size;
+    let start = i64::try_from(start)
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .ok_or_else(|| LCoreError::InvalidInput(format!("Timestamp out of range: {}", timestamp)))?;
+    Ok(format!("{}:{}", label, start.to_rfc3339()))
+}
+
+/// Update every window aggregate touched by an accepted reading and refresh
+/// the published rows in `analytics` (metric type `<field>_<stat>`).
+/// Returns the number of (field, window) aggregates updated; an aggregate the
+/// reading would overflow is left as it was. Only the first `max_fields`
+/// fields by name are aggregated, so a reading cannot fan out into an
+/// unbounded number of rows.
+pub fn record_reading(
+    db: &Database,
+    device_id: &str,
+    data: &[u8],
+    max_fields: usize,
+    metadata: &AdvanceMetadata,
+) -> Result<usize, LCoreError> {
+    let mut fields = numeric_fields(data);
+    if fields.len() > max_fields {
+        warn!(
+            "Skipping analytics of {} fields of a reading from device {}: at most {} are aggregated",
+            fields.len() - max_fields,
+            device_id,
+            max_fields
+        );
+        fields.truncate(max_fields);
+    }
+    let mut updated = 0;
+
+    for (field, value) in &fields {
+        for (label, size) in WINDOWS {
+            let time_window = window_label(label, size, metadata.timestamp)?;
+            let stats = match db.get_analytics_window(device_id, field, &time_window)? {
+                Some(mut stats) => {
+                    stats.push(*value);
+                    stats
+                }
+                None => RunningStats::new(*value),
+            };
+            if !stats.is_finite() {
+                warn!("Skipping {} analytics for device {}: aggregate would overflow", field, device_id);
+                continue;
+            }
+            db.upsert_analytics_window(device_id, field, &time_window, &stats)?;
+
+            for (stat, stat_value) in stats.published() {
+                let metric_type = format!("{}_{}", field, stat);
+                db.insert_analytics(device_id, &metric_type, stat_value, &time_window, metadata)?;
+            }
+            updated += 1;
+        }
+    }
+
+    Ok(updated)
+}
+
+/// ABI-encoded `(string device, string metric, string window, int256 value)`
+/// notice payload; `value` is scaled by `10^NOTICE_VALUE_DECIMALS` and rounded
+/// since Solidity has no floating point.
+pub fn notice_payload(device_id: &str, metric_type: &str, time_window: &str, value: f64) -> Result<Vec<u8>, LCoreError> {
+    let scaled = (value * 10f64.powi(NOTICE_VALUE_DECIMALS as i32)).round();
+    if !scaled.is_finite() || scaled.abs() >= i128::MAX as f64 {
+        return Err(LCoreError::InvalidInput(format!("Analytics value out of range: {}", value)));
+    }
+    Ok(abi::encode(&[
+        Token::String(device_id.to_string()),
+        Token::String(metric_type.to_string()),
+        Token::String(time_window.to_string()),
+        Token::Int(scaled as i128),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_running_stats() {
+        let mut stats = RunningStats::new(2.0);
+        for value in [4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.push(value);
+        }
+        assert_eq!(stats.count, 8);
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!(stats.variance(), 4.0);
+        assert_eq!(stats.min, 2.0);
+        assert_eq!(stats.max, 9.0);
+    }
+
+    #[test]
+    fn test_numeric_fields() {
+        let fields = numeric_fields(br#"{"temperature": 4.5, "label": "fridge", "humidity": 40, "door_open": true}"#);
+        assert_eq!(fields, vec![("humidity".to_string(), 40.0), ("temperature".to_string(), 4.5)]);
+        assert!(numeric_fields(b"temperature:23.5").is_empty());
+    }
+
+    #[test]
+    fn test_window_label() {
+        // 2024-01-01T10:30:00Z
+        let timestamp = 1_704_105_000;
+        assert_eq!(window_label("1h", 3_600, timestamp).unwrap(), "1h:2024-01-01T10:00:00+00:00");
+        assert_eq!(window_label("1d", 86_400, timestamp).unwrap(), "1d:2024-01-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_notice_payload() {
+        let payload = notice_payload("did:example:1", "temperature_mean", "1h:2024-01-01T10:00:00+00:00", -4.25).unwrap();
+        // Three string offsets, then the value word
+        assert_eq!(&payload[96..128], &abi::encode(&[Token::Int(-4_250_000)])[..]);
+        assert!(notice_payload("did:example:1", "x", "1h", f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn test_record_reading() {
+        let db = Database::open(":memory:").unwrap();
+        let mut metadata = AdvanceMetadata::for_test("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266", 1, 1_704_105_000);
+
+        assert_eq!(record_reading(&db, "did:example:1", br#"{"temperature": 4.0}"#, 16, &metadata).unwrap(), 2);
+        metadata.input_index = 2;
+        record_reading(&db, "did:example:1", br#"{"temperature": 6.0}"#, 16, &metadata).unwrap();
+
+        let means = db.get_analytics("did:example:1", "temperature_mean").unwrap();
+        assert_eq!(means.len(), 2);
+        assert!(means.iter().all(|row| row.value == 5.0));
+
+        let variance = db.get_analytics("did:example:1", "temperature_variance").unwrap();
+        assert!(variance.iter().all(|row| row.value == 1.0));
+    }
+
+    #[test]
+    fn test_record_reading_skips_overflowing_aggregates() {
+        let db = Database::open(":memory:").unwrap();
+        let mut metadata = AdvanceMetadata::for_test("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266", 1, 1_704_105_000);
+
+        assert_eq!(record_reading(&db, "did:example:1", br#"{"x": 1e308}"#, 16, &metadata).unwrap(), 2);
+        metadata.input_index = 2;
+        assert_eq!(record_reading(&db, "did:example:1", br#"{"x": -1e308}"#, 16, &metadata).unwrap(), 0);
+        // The variance of 1e308 and 1 does not fit in an f64 either
+        metadata.input_index = 3;
+        assert_eq!(record_reading(&db, "did:example:1", br#"{"x": 1}"#, 16, &metadata).unwrap(), 0);
+
+        let stats = db.get_analytics_window("did:example:1", "x", &window_label("1h", 3_600, 1_704_105_000).unwrap()).unwrap().unwrap();
+        assert_eq!(stats, RunningStats::new(1e308));
+    }
+
+    #[test]
+    fn test_record_reading_caps_fields() {
+        let db = Database::open(":memory:").unwrap();
+        let metadata = AdvanceMetadata::for_test("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266", 1, 1_704_105_000);
+
+        // Fields are taken in name order, two windows each
+        assert_eq!(record_reading(&db, "did:example:1", br#"{"c": 3, "a": 1, "b": 2}"#, 2, &metadata).unwrap(), 4);
+        assert_eq!(db.get_analytics("did:example:1", "b_mean").unwrap().len(), 2);
+        assert!(db.get_analytics("did:example:1", "c_mean").unwrap().is_empty());
+    }
+}
@@ -1,12 +1,357 @@
-use axum::{routing::get, Router};
-use crate::AppState;
+// lcore-node/src/api.rs
+//
+// Local developer API: the node's database and handlers over plain HTTP, so
+// front-ends can be built without a rollups node. Never part of a Cartesi
+// machine; readings are decrypted for whoever asks.
 
-pub fn create_router(_state: AppState) -> Router {
+use crate::app::{decrypt_reading, App, Policies};
+use crate::database::{Database, DeviceRow};
+use crate::error::LCoreError;
+use crate::payloads::WrappedPayload;
+use crate::rollup::{normalize_address, AdvanceMetadata};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use json::object;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Mutex as AsyncMutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Sender used for submissions that do not name one
+const DEFAULT_SENDER: &str = "0x0000000000000000000000000000000000000000";
+const DEFAULT_HISTORY_LIMIT: u32 = 50;
+
+/// Shared by every request; the node is used by one request at a time.
+/// Waiting for it never blocks a runtime worker, which must stay free to
+/// serve the output collector while an input is dispatched.
+pub struct ApiState {
+    node: AsyncMutex<DevNode>,
+    /// Outputs the dispatcher sent while handling the current input
+    outputs: Arc<Mutex<Outputs>>,
+    collector: JoinHandle<()>,
+}
+
+struct DevNode {
+    /// Sends its outputs to the local collector instead of a rollup server
+    app: App,
+    /// Stands in for the InputBox index, continuing after what is already stored
+    next_input_index: u64,
+}
+
+impl DevNode {
+    /// Metadata for the next developer input from `sender`. Wall-clock time
+    /// is fine here: nothing replays developer inputs.
+    fn next_metadata(&mut self, sender: &str) -> Result<AdvanceMetadata, LCoreError> {
+        let metadata = AdvanceMetadata {
+            msg_sender: normalize_address(sender)?,
+            epoch_index: 0,
+            input_index: self.next_input_index,
+            block_number: self.next_input_index,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| LCoreError::Internal(e.to_string()))?
+                .as_secs(),
+        };
+        self.next_input_index += 1;
+        Ok(metadata)
+    }
+}
+
+impl ApiState {
+    /// Also starts the output collector on an ephemeral local port, which
+    /// stands in for the rollup server's output endpoints.
+    pub async fn new(db: Database, policies: Policies) -> Result<Self, LCoreError> {
+        let next_input_index = db.max_input_index()?.map_or(0, |index| index + 1);
+
+        let outputs = Arc::new(Mutex::new(Outputs::default()));
+        let collector = axum::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .map_err(|e| LCoreError::Internal(format!("Output collector failed to bind: {}", e)))?
+            .serve(Router::new().route("/:kind", post(collect_output)).with_state(outputs.clone()).into_make_service());
+        let app = App::new(db, policies, &format!("http://{}", collector.local_addr()));
+        let collector = tokio::spawn(async move {
+            let _ = collector.await;
+        });
+
+        Ok(Self { node: AsyncMutex::new(DevNode { app, next_input_index }), outputs, collector })
+    }
+}
+
+impl Drop for ApiState {
+    fn drop(&mut self) {
+        self.collector.abort();
+    }
+}
+
+pub fn create_router(state: Arc<ApiState>) -> Router {
     Router::new()
         .route("/health", get(health_check))
-        // More routes will be added here
+        .route("/devices", get(list_devices))
+        .route("/devices/:device_id", get(get_device))
+        .route("/devices/:device_id/readings", get(get_readings))
+        .route("/devices/:device_id/analytics", get(get_analytics))
+        .route("/devices/:device_id/alerts", get(get_alerts))
+        .route("/inputs", post(submit_input))
+        .with_state(state)
+}
+
+/// Serve the developer API on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, state: Arc<ApiState>) -> Result<(), LCoreError> {
+    info!("Developer API listening on {}", addr);
+    axum::Server::bind(&addr)
+        .serve(create_router(state).into_make_service())
+        .await
+        .map_err(|e| LCoreError::Internal(format!("Developer API failed: {}", e)))
 }
 
+/// Error body `{"error": ...}` with a status code
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<LCoreError> for ApiError {
+    fn from(e: LCoreError) -> Self {
+        ApiError(e.status_code(), e.to_string())
+    }
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+
 async fn health_check() -> &'static str {
     "OK"
-} 
\ No newline at end of file
+}
+
+fn device_json(device: &DeviceRow) -> Value {
+    json!({
+        "id": device.id,
+        "owner": device.owner,
+        "status": device.status.as_str(),
+        "device_type": device.device_type,
+        "did_document": device.did_document,
+        "public_key": device.public_key,
+        "created_at": device.created_at,
+        "input_index": device.input_index,
+    })
+}
+
+fn not_found(device_id: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("device {} is not registered", device_id))
+}
+
+async fn list_devices(State(state): State<Arc<ApiState>>) -> ApiResult {
+    let node = state.node.lock().await;
+    let devices: Vec<Value> = node.app.db().list_devices()?.iter().map(device_json).collect();
+    Ok(Json(json!({ "devices": devices })))
+}
+
+async fn get_device(State(state): State<Arc<ApiState>>, Path(device_id): Path<String>) -> ApiResult {
+    let node = state.node.lock().await;
+    let device = node.app.db().get_device(&device_id)?.ok_or_else(|| not_found(&device_id))?;
+    Ok(Json(device_json(&device)))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    limit: Option<u32>,
+}
+
+/// Decrypted readings of a device, newest first
+async fn get_readings(
+    State(state): State<Arc<ApiState>>,
+    Path(device_id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult {
+    let node = state.node.lock().await;
+    let rows = node.app.db().get_sensor_history(&device_id, query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT))?;
+    let readings = rows
+        .into_iter()
+        .map(|row| decrypt_reading(row, &node.app.policies().encryption_context))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(json!({ "device_id": device_id, "readings": readings })))
+}
+
+#[derive(Deserialize)]
+struct AnalyticsQuery {
+    metric: String,
+}
+
+async fn get_analytics(
+    State(state): State<Arc<ApiState>>,
+    Path(device_id): Path<String>,
+    Query(query): Query<AnalyticsQuery>,
+) -> ApiResult {
+    let node = state.node.lock().await;
+    let analytics = node.app.db().get_analytics(&device_id, &query.metric)?;
+    Ok(Json(json!({ "device_id": device_id, "metric": query.metric, "analytics": analytics })))
+}
+
+async fn get_alerts(State(state): State<Arc<ApiState>>, Path(device_id): Path<String>) -> ApiResult {
+    let node = state.node.lock().await;
+    let alerts = node.app.db().get_alerts(&device_id)?;
+    Ok(Json(json!({ "device_id": device_id, "alerts": alerts })))
+}
+
+/// Any advance input, plus the address it would have been sent from
+#[derive(Deserialize)]
+struct InputRequest {
+    sender: Option<String>,
+    #[serde(flatten)]
+    input: WrappedPayload,
+}
+
+/// Outputs sent to the collector, grouped by kind in the order they were sent
+#[derive(Default)]
+struct Outputs {
+    notices: Vec<Value>,
+    reports: Vec<Value>,
+    vouchers: Vec<Value>,
+}
+
+/// JSON outputs as JSON, anything else (ABI notices) as `0x`-prefixed hex
+fn output_json(payload: &[u8]) -> Value {
+    serde_json::from_slice(payload).unwrap_or_else(|_| Value::String(format!("0x{}", hex::encode(payload))))
+}
+
+/// `/notice`, `/report`, `/voucher` and `/exception` of the output collector
+async fn collect_output(
+    State(outputs): State<Arc<Mutex<Outputs>>>,
+    Path(kind): Path<String>,
+    Json(body): Json<Value>,
+) -> StatusCode {
+    let payload = match body["payload"].as_str().map(|payload| hex::decode(payload.trim_start_matches("0x"))) {
+        Some(Ok(payload)) => payload,
+        _ => return StatusCode::BAD_REQUEST,
+    };
+    let mut outputs = outputs.lock().unwrap();
+    match kind.as_str() {
+        "notice" => outputs.notices.push(output_json(&payload)),
+        "report" => outputs.reports.push(output_json(&payload)),
+        "voucher" => outputs.vouchers.push(json!({
+            "destination": body["destination"],
+            "payload": format!("0x{}", hex::encode(&payload)),
+        })),
+        // Raised again as the error `App::handle` returns
+        "exception" => {}
+        _ => return StatusCode::NOT_FOUND,
+    }
+    StatusCode::OK
+}
+
+/// Runs `{action, payload}` through the same dispatcher as the rollup loop
+/// and returns the finish status with the notices, reports and vouchers the
+/// input produced.
+async fn submit_input(State(state): State<Arc<ApiState>>, Json(request): Json<InputRequest>) -> ApiResult {
+    let sender = request.sender.unwrap_or_else(|| DEFAULT_SENDER.to_string());
+    let input = json!({ "action": request.input.action, "payload": request.input.payload }).to_string();
+
+    // The database connection cannot be shared between threads, so the
+    // dispatcher runs to completion on a blocking thread that owns the lock
+    let runtime = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut node = state.node.blocking_lock();
+        let metadata = node.next_metadata(&sender)?;
+        let request = object! {
+            "request_type" => "advance_state",
+            "data" => object! {
+                "metadata" => object! {
+                    "msg_sender" => metadata.msg_sender.as_str(),
+                    "epoch_index" => metadata.epoch_index,
+                    "input_index" => metadata.input_index,
+                    "block_number" => metadata.block_number,
+                    "timestamp" => metadata.timestamp,
+                },
+                "payload" => format!("0x{}", hex::encode(input)),
+            },
+        };
+
+        *state.outputs.lock().unwrap() = Outputs::default();
+        let status = runtime.block_on(node.app.handle(request))?;
+        let outputs = std::mem::take(&mut *state.outputs.lock().unwrap());
+        Ok(Json(json!({
+            "status": status,
+            "input_index": metadata.input_index,
+            "notices": outputs.notices,
+            "reports": outputs.reports,
+            "vouchers": outputs.vouchers,
+        })))
+    })
+    .await
+    .map_err(|e| LCoreError::Internal(format!("Dispatcher task failed: {}", e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// POST `body` as JSON and return the status with the parsed response
+    async fn post(url: String, body: Value) -> (StatusCode, Value) {
+        let request = hyper::Request::post(url)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(body.to_string()))
+            .unwrap();
+        let response = hyper::Client::new().request(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn get_json(url: String) -> (StatusCode, Value) {
+        let response = hyper::Client::new().get(url.parse().unwrap()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_register_submit_then_read_back() {
+        let policies = Policies::from_config(&Config::default()).unwrap();
+        let state = Arc::new(ApiState::new(Database::open(":memory:").unwrap(), policies).await.unwrap());
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(create_router(state).into_make_service());
+        let base = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let (status, registered) = post(
+            format!("{}/inputs", base),
+            json!({ "action": "register", "payload": { "device_id": "did:example:dev", "did_document": "{}", "public_key": "{}" } }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(registered["status"], "accept");
+        assert_eq!(registered["input_index"], 0);
+
+        let (_, rejected) = post(format!("{}/inputs", base), json!({ "action": "teleport", "payload": {} })).await;
+        assert_eq!(rejected["status"], "reject");
+        assert_eq!(rejected["reports"][0]["kind"], "invalid_input");
+
+        let (status, submitted) = post(
+            format!("{}/inputs", base),
+            json!({ "action": "submit", "payload": { "device_id": "did:example:dev", "data": hex::encode(br#"{"temperature":21.5}"#) } }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(submitted["status"], "accept");
+        assert_eq!(submitted["input_index"], 2);
+
+        let (_, devices) = get_json(format!("{}/devices", base)).await;
+        assert_eq!(devices["devices"][0]["id"], "did:example:dev");
+        assert_eq!(devices["devices"][0]["owner"], DEFAULT_SENDER);
+
+        let (_, history) = get_json(format!("{}/devices/did:example:dev/readings", base)).await;
+        assert_eq!(history["readings"][0]["decrypted_payload"], r#"{"temperature":21.5}"#);
+
+        let (status, _) = get_json(format!("{}/devices/did:example:none", base)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,1310 @@
+// lcore-node/src/app.rs
+//
+// The node as a library: request dispatch, the advance and inspect handlers,
+// and the policies they enforce
+
+use crate::config::{Config, FeatureConfig};
+use crate::database::{self, AlertRow, Database, DeviceStatus, RuleRow};
+use crate::encryption::{derive_stage1_nonce, derive_stage2_nonce};
+use crate::encryption::{Stage1Encryption, Stage2Encryption};
+use crate::error::{ErrorClass, LCoreError};
+use crate::health::HealthState;
+use crate::logging::redacted;
+use crate::metrics::METRICS;
+use crate::payloads::*;
+use crate::permissions::Permissions;
+use crate::portals::{self, PortalKind, Portals};
+use crate::rollup::{self, normalize_address, AdvanceMetadata};
+use crate::staking::StakePolicy;
+use crate::vouchers::{RewardPolicy, Voucher};
+use crate::{access, analytics, batch, codec, device_auth, rules, schema};
+use hyper::client::HttpConnector;
+use json::{object, JsonValue};
+use sha2::{Digest, Sha256};
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+
+/// The node's state machine: its database, the genesis policies and the
+/// rollup server the handlers send outputs to.
+pub struct App {
+    db: Database,
+    policies: Policies,
+    client: hyper::Client<HttpConnector>,
+    server_addr: String,
+}
+
+impl App {
+    pub fn new(db: Database, policies: Policies, server_addr: &str) -> Self {
+        Self {
+            db,
+            policies,
+            client: hyper::Client::new(),
+            server_addr: server_addr.to_string(),
+        }
+    }
+
+    pub fn db(&self) -> &Database {
+        &self.db
+    }
+
+    pub fn policies(&self) -> &Policies {
+        &self.policies
+    }
+
+    /// Handle one request returned by `/finish`; the result is the status
+    /// to send with the next `/finish`.
+    ///
+    /// Client errors reject the input with an error report. Server errors are
+    /// raised to the rollup server through `/exception` and returned, since
+    /// the node cannot tell what state the input should have produced.
+    /// Either way nothing the request wrote is kept unless it was accepted,
+    /// as the rollups node discards the machine state of rejected inputs.
+    pub async fn handle(&self, request: JsonValue) -> Result<&'static str, LCoreError> {
+        let request_type = request["request_type"]
+            .as_str()
+            .ok_or_else(|| LCoreError::Protocol("request_type is not a string".to_string()))?
+            .to_string();
+        let savepoint = self.db.savepoint()?;
+        let result = match request_type.as_str() {
+            "advance_state" => self.advance(request).await,
+            "inspect_state" => self.inspect(request).await,
+            other => Err(LCoreError::Protocol(format!("Unknown request type {}", other))),
+        };
+        match result {
+            Ok("accept") => savepoint.commit()?,
+            _ => drop(savepoint),
+        }
+        let status = match result {
+            Ok(status) => status,
+            Err(e) if e.class() == ErrorClass::Client => {
+                // Rejection messages can quote the reading that failed validation
+                warn!(kind = e.kind(), "Rejecting {}: {}", request_type, redacted(&e));
+                rollup::send_report(&self.client, &self.server_addr, e.report().dump().as_bytes()).await?;
+                "reject"
+            }
+            Err(e) => {
+                error!(kind = e.kind(), "Failed to process {}: {}", request_type, e);
+                METRICS.record_input(&request_type, "exception");
+                rollup::send_exception(&self.client, &self.server_addr, e.to_string().as_bytes()).await?;
+                return Err(e);
+            }
+        };
+        METRICS.record_input(&request_type, status);
+        Ok(status)
+    }
+
+    pub async fn advance(&self, request: JsonValue) -> Result<&'static str, LCoreError> {
+        handle_advance(&self.db, &self.policies, &self.client, &self.server_addr, request).await
+    }
+
+    pub async fn inspect(&self, request: JsonValue) -> Result<&'static str, LCoreError> {
+        handle_inspect(&self.db, &self.policies, &self.client, &self.server_addr, request).await
+    }
+
+    /// The rollup loop: report the previous status to `/finish` and handle
+    /// whatever request comes back. Only returns on a server error.
+    pub async fn run(&self, health: &HealthState) -> Result<(), LCoreError> {
+        let mut status = "accept";
+        loop {
+            debug!("Sending finish");
+            let response = object! {"status" => status};
+            let request = hyper::Request::builder()
+                .method(hyper::Method::POST)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .uri(format!("{}/finish", &self.server_addr))
+                .body(hyper::Body::from(response.dump()))
+                .map_err(|e| LCoreError::Rollup(format!("Failed to build finish request: {}", e)))?;
+            let response = self
+                .client
+                .request(request)
+                .await
+                .map_err(|e| LCoreError::Rollup(format!("Failed to send finish: {}", e)))?;
+            debug!("Received finish status {}", response.status());
+            if response.status().is_success() {
+                health.record_finish();
+            }
+
+            if response.status() == hyper::StatusCode::ACCEPTED {
+                debug!("No pending rollup request, trying again");
+                continue;
+            }
+            let body = hyper::body::to_bytes(response)
+                .await
+                .map_err(|e| LCoreError::Rollup(format!("Failed to read finish response: {}", e)))?;
+            let req = std::str::from_utf8(&body)
+                .ok()
+                .and_then(|utf| json::parse(utf).ok())
+                .ok_or_else(|| LCoreError::Protocol("finish response is not JSON".to_string()))?;
+
+            let advance = req["request_type"] == "advance_state";
+            let input_index = req["data"]["metadata"]["input_index"].as_u64();
+            status = self.handle(req).await?;
+            if advance {
+                health.refresh(&self.db, input_index);
+            }
+        }
+    }
+}
+
+/// Result of authenticating and storing a single reading
+pub(crate) enum ReadingOutcome {
+    /// Stored; carries the outputs the reading produced
+    Accepted(ReadingOutputs),
+    Rejected(String),
+}
+
+/// Notices and vouchers raised by accepted readings, sent once they are final
+#[derive(Default)]
+pub(crate) struct ReadingOutputs {
+    pub(crate) alerts: Vec<AlertRow>,
+    pub(crate) slashes: Vec<SlashEvent>,
+    pub(crate) vouchers: Vec<Voucher>,
+}
+
+impl ReadingOutputs {
+    pub(crate) fn extend(&mut self, other: ReadingOutputs) {
+        self.alerts.extend(other.alerts);
+        self.slashes.extend(other.slashes);
+        self.vouchers.extend(other.vouchers);
+    }
+}
+
+/// Stake moved from a device to the treasury
+pub(crate) struct SlashEvent {
+    pub(crate) device_id: String,
+    pub(crate) amount: u128,
+    pub(crate) reason: String,
+}
+
+/// Deployment policies, fixed at genesis and shared by every handler
+pub struct Policies {
+    pub(crate) permissions: Permissions,
+    pub(crate) portals: Portals,
+    pub(crate) rewards: Option<RewardPolicy>,
+    pub(crate) staking: Option<StakePolicy>,
+    pub(crate) encryption_context: String,
+    pub(crate) require_device_signature: bool,
+    pub(crate) features: FeatureConfig,
+}
+
+impl Policies {
+    pub fn from_config(config: &Config) -> Result<Self, LCoreError> {
+        Ok(Self {
+            permissions: config.permissions()?,
+            portals: config.portals()?,
+            rewards: config.reward_policy()?,
+            staking: config.stake_policy()?,
+            encryption_context: config.encryption_context.clone(),
+            require_device_signature: config.auth.require_device_signature,
+            features: config.features.clone(),
+        })
+    }
+}
+
+async fn handle_advance(
+    db: &Database,
+    policies: &Policies,
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    request: JsonValue,
+) -> Result<&'static str, LCoreError> {
+    let permissions = &policies.permissions;
+    let metadata = AdvanceMetadata::from_request(&request)?;
+    let payload_str = request["data"]["payload"]
+        .as_str()
+        .ok_or_else(|| LCoreError::Protocol("Missing payload".to_string()))?;
+    info!(
+        input_index = metadata.input_index,
+        msg_sender = %metadata.msg_sender,
+        payload = %redacted(payload_str),
+        "Received advance request"
+    );
+    let payload_bytes = hex::decode(payload_str.trim_start_matches("0x"))?;
+
+    // The relay tells the dApp its own address, needed for Ether withdrawals
+    if policies.portals.is_dapp_address_relay(&metadata.msg_sender) {
+        let address = match portals::decode_dapp_address(&payload_bytes) {
+            Ok(address) => address,
+            Err(e) => {
+                return Err(LCoreError::InvalidInput(format!("Rejecting malformed dApp address relay input: {}", e)));
+            }
+        };
+        db.set_dapp_address(&address, &metadata)?;
+        info!("dApp address set to {}", address);
+        return Ok("accept");
+    }
+
+    // Portal inputs carry packed deposit payloads, not action envelopes
+    if let Some(kind) = policies.portals.portal_kind(&metadata.msg_sender) {
+        return handle_deposit(db, client, server_addr, kind, &payload_bytes, &metadata).await;
+    }
+
+    // JSON envelope, or compact CBOR when the payload starts with the version byte
+    let wrapped: WrappedPayload = serde_json::from_value(codec::decode_envelope(&payload_bytes)?)?;
+
+    match wrapped.action.as_str() {
+        "register" => {
+            let reg: RegisterPayload = serde_json::from_value(wrapped.payload)?;
+
+            let mut authorized_senders = Vec::with_capacity(reg.authorized_senders.len());
+            for sender in &reg.authorized_senders {
+                match normalize_address(sender) {
+                    Ok(address) => authorized_senders.push(address),
+                    Err(e) => {
+                        return Err(LCoreError::Policy(format!("Rejecting registration of {}: {}", reg.device_id, e)));
+                    }
+                }
+            }
+
+            if let Some(device_type) = &reg.device_type {
+                if db.get_device_schema(device_type)?.is_none() {
+                    return Err(LCoreError::Policy(format!(
+                        "Rejecting registration of {}: unknown device type {}",
+                        reg.device_id, device_type
+                    )));
+                }
+            }
+
+            if db.get_device(&reg.device_id)?.is_some() {
+                return Err(LCoreError::Policy(format!(
+                    "Rejecting registration of {}: device is already registered",
+                    reg.device_id
+                )));
+            }
+
+            // New devices lock the configured stake from the owner's balance
+            if let Some(staking) = &policies.staking {
+                if db.get_stake(&reg.device_id)?.is_some() {
+                    return Err(LCoreError::Policy(format!(
+                        "Rejecting registration of {}: the stake of its previous registration is still unbonding",
+                        reg.device_id
+                    )));
+                }
+                if !staking.lock(db, &reg.device_id, &metadata.msg_sender, &metadata)? {
+                    return Err(LCoreError::Policy(format!(
+                        "Rejecting registration of {}: {} has not deposited the {} {} stake",
+                        reg.device_id, metadata.msg_sender, staking.amount, staking.asset.as_str()
+                    )));
+                }
+            }
+
+            // Insert device and initialise counter
+            db.insert_device(
+                &reg.device_id,
+                &reg.did_document,
+                &reg.public_key,
+                reg.device_type.as_deref(),
+                &authorized_senders,
+                &metadata,
+            )?;
+            info!("Device {} registered", reg.device_id);
+            Ok("accept")
+        }
+        "submit" => {
+            let data_pl: DataPayload = serde_json::from_value(wrapped.payload)?;
+            match process_reading(db, &data_pl, &metadata, false, policies)? {
+                ReadingOutcome::Accepted(outputs) => {
+                    send_reading_outputs(client, server_addr, &outputs).await?;
+                    info!("Successfully authenticated, processed, encrypted, and stored IoT data input.");
+                    Ok("accept")
+                }
+                ReadingOutcome::Rejected(reason) => {
+                    warn!("Submission rejected: {}", redacted(&reason));
+                    let report = object! {
+                        "type" => "submit_result",
+                        "accepted" => false,
+                        "device_id" => data_pl.device_id.as_str(),
+                        "reason" => reason,
+                    };
+                    rollup::send_report(client, server_addr, report.dump().as_bytes()).await?;
+                    Ok("reject")
+                }
+            }
+        }
+        "register_schema" => {
+            let schema_pl: SchemaPayload = serde_json::from_value(wrapped.payload)?;
+            if !permissions.is_admin(&metadata.msg_sender) {
+                return Err(LCoreError::Policy(format!(
+                    "register_schema rejected: {} is not an admin",
+                    metadata.msg_sender
+                )));
+            }
+            let schema_json = schema_pl.schema.to_string();
+            if let Err(e) = schema::ReadingSchema::parse(&schema_json) {
+                return Err(LCoreError::Policy(format!("register_schema rejected: {}", e)));
+            }
+            let version = db.upsert_device_schema(&schema_pl.device_type, &schema_json, &metadata)?;
+            info!("Schema for device type {} registered (version {})", schema_pl.device_type, version);
+            Ok("accept")
+        }
+        "submit_batch" => {
+            let batch_pl: BatchPayload = serde_json::from_value(wrapped.payload)?;
+            handle_batch(db, policies, client, server_addr, batch_pl, &metadata).await
+        }
+        "update_did" | "rotate_key" | "deactivate" | "reactivate" => {
+            let lifecycle: LifecyclePayload = serde_json::from_value(wrapped.payload)?;
+            handle_lifecycle(db, permissions, &wrapped.action, lifecycle, &metadata)
+        }
+        "grant_access" | "revoke_access" => {
+            let access: AccessPayload = serde_json::from_value(wrapped.payload)?;
+            let device = match db.get_device(&access.device_id)? {
+                Some(device) => device,
+                None => {
+                    return Err(LCoreError::Policy(format!(
+                        "{} rejected: device {} is not registered",
+                        wrapped.action, access.device_id
+                    )));
+                }
+            };
+            if !permissions.can_manage(&metadata.msg_sender, &device) {
+                return Err(LCoreError::Policy(format!(
+                    "{} rejected: {} may not manage access to {}",
+                    wrapped.action, metadata.msg_sender, device.id
+                )));
+            }
+            let grantee = match normalize_address(&access.grantee) {
+                Ok(address) => address,
+                Err(e) => {
+                    return Err(LCoreError::Policy(format!("{} rejected: {}", wrapped.action, e)));
+                }
+            };
+
+            if wrapped.action == "grant_access" {
+                db.grant_access(&device.id, &grantee, &metadata)?;
+                info!("Granted {} access to device {}", grantee, device.id);
+            } else if db.revoke_access(&device.id, &grantee)? {
+                info!("Revoked access of {} to device {}", grantee, device.id);
+            } else if db.get_purchased_grant(&device.id, &grantee)?.is_some() {
+                return Err(LCoreError::Policy(format!(
+                    "revoke_access rejected: {} paid for its grant for {}",
+                    grantee, device.id
+                )));
+            } else {
+                return Err(LCoreError::Policy(format!(
+                    "revoke_access rejected: {} holds no grant for {}",
+                    grantee, device.id
+                )));
+            }
+            Ok("accept")
+        }
+        "set_access_price" => {
+            let price_pl: AccessPricePayload = serde_json::from_value(wrapped.payload)?;
+            let device = match db.get_device(&price_pl.device_id)? {
+                Some(device) => device,
+                None => {
+                    return Err(LCoreError::Policy(format!(
+                        "set_access_price rejected: device {} is not registered",
+                        price_pl.device_id
+                    )));
+                }
+            };
+            if !permissions.can_manage(&metadata.msg_sender, &device) {
+                return Err(LCoreError::Policy(format!(
+                    "set_access_price rejected: {} may not price {}",
+                    metadata.msg_sender, device.id
+                )));
+            }
+            let asset = match portals::Asset::parse(&price_pl.asset) {
+                Ok(asset) => asset,
+                Err(e) => {
+                    return Err(LCoreError::Policy(format!("set_access_price rejected: {}", e)));
+                }
+            };
+            let price = match price_pl.price.parse::<u128>() {
+                Ok(price) if price > 0 && price_pl.period_seconds != Some(0) => price,
+                _ => {
+                    return Err(LCoreError::Policy("set_access_price rejected: price and period must be positive".to_string()));
+                }
+            };
+            if price_pl.period_seconds.is_some_and(|period| period > access::MAX_STORED) {
+                return Err(LCoreError::Policy(format!(
+                    "set_access_price rejected: period may not exceed {} seconds",
+                    access::MAX_STORED
+                )));
+            }
+            db.set_access_price(&access::AccessPrice {
+                device_id: device.id.clone(),
+                asset,
+                price,
+                period_seconds: price_pl.period_seconds,
+            }, &metadata)?;
+            info!("Access to device {} priced at {} {}", device.id, price, price_pl.asset);
+            Ok("accept")
+        }
+        "purchase_access" => {
+            let purchase_pl: PurchaseAccessPayload = serde_json::from_value(wrapped.payload)?;
+            let outcome = match db.get_device(&purchase_pl.device_id)? {
+                Some(device) => access::purchase(db, &device, purchase_pl.quantity, &metadata)?,
+                None => Err(format!("Device {} is not registered", purchase_pl.device_id)),
+            };
+            // A refused purchase is reported once, as the rejection error
+            let cost = outcome.map_err(|reason| LCoreError::Policy(format!("purchase_access rejected: {}", reason)))?;
+            let report = object! {
+                "type" => "purchase_result",
+                "accepted" => true,
+                "device_id" => purchase_pl.device_id.as_str(),
+                "cost" => cost.to_string(),
+            };
+            rollup::send_report(client, server_addr, report.dump().as_bytes()).await?;
+            info!("{} bought access to {} for {}", metadata.msg_sender, purchase_pl.device_id, cost);
+            Ok("accept")
+        }
+        "set_reader_key" => {
+            let key_pl: ReaderKeyPayload = serde_json::from_value(wrapped.payload)?;
+            if let Err(e) = device_auth::validate_public_jwk(&key_pl.public_key) {
+                return Err(LCoreError::Policy(format!("set_reader_key rejected: {}", e)));
+            }
+            db.set_reader_key(&metadata.msg_sender, &key_pl.public_key, &metadata)?;
+            info!("Reader key set for {}", metadata.msg_sender);
+            Ok("accept")
+        }
+        "add_rule" => {
+            let rule_pl: AddRulePayload = serde_json::from_value(wrapped.payload)?;
+            let rule = RuleRow {
+                id: 0,
+                device_id: rule_pl.device_id,
+                device_type: rule_pl.device_type,
+                field: rule_pl.field,
+                comparator: rule_pl.comparator,
+                threshold: rule_pl.threshold,
+                hysteresis: rule_pl.hysteresis,
+                slash_amount: match rule_pl.slash_amount.as_deref().map(str::parse::<u128>) {
+                    None => None,
+                    Some(Ok(amount)) if amount > 0 => Some(amount),
+                    Some(_) => {
+                        return Err(LCoreError::Policy("add_rule rejected: slash_amount must be a positive integer".to_string()));
+                    }
+                },
+            };
+            if let Err(e) = rules::validate_rule(&rule) {
+                return Err(LCoreError::Policy(format!("add_rule rejected: {}", e)));
+            }
+            // Only arbiters may put a device's stake at stake
+            if rule.slash_amount.is_some() && !permissions.is_admin(&metadata.msg_sender) {
+                return Err(LCoreError::Policy("add_rule rejected: only admins may add slashing rules".to_string()));
+            }
+            if !can_manage_rule(db, permissions, &metadata.msg_sender, &rule)? {
+                return Err(LCoreError::Policy(format!(
+                    "add_rule rejected: {} may not manage this rule",
+                    metadata.msg_sender
+                )));
+            }
+            let rule_id = db.insert_rule(&rule, &metadata)?;
+            info!("Rule #{} added: {} {} {}", rule_id, rule.field, rule.comparator.as_str(), rule.threshold);
+            Ok("accept")
+        }
+        "remove_rule" => {
+            let remove: RemoveRulePayload = serde_json::from_value(wrapped.payload)?;
+            let rule = match db.get_rule(remove.rule_id)? {
+                Some(rule) => rule,
+                None => {
+                    return Err(LCoreError::Policy(format!(
+                        "remove_rule rejected: rule #{} does not exist",
+                        remove.rule_id
+                    )));
+                }
+            };
+            if !can_manage_rule(db, permissions, &metadata.msg_sender, &rule)? {
+                return Err(LCoreError::Policy(format!(
+                    "remove_rule rejected: {} may not manage rule #{}",
+                    metadata.msg_sender, rule.id
+                )));
+            }
+            db.remove_rule(rule.id)?;
+            info!("Rule #{} removed", rule.id);
+            Ok("accept")
+        }
+        "publish_analytics" => {
+            let publish: PublishAnalyticsPayload = serde_json::from_value(wrapped.payload)?;
+            let device = match db.get_device(&publish.device_id)? {
+                Some(device) => device,
+                None => {
+                    return Err(LCoreError::Policy(format!(
+                        "publish_analytics rejected: device {} is not registered",
+                        publish.device_id
+                    )));
+                }
+            };
+            if !permissions.can_manage(&metadata.msg_sender, &device) {
+                return Err(LCoreError::Policy(format!(
+                    "publish_analytics rejected: {} may not publish analytics of {}",
+                    metadata.msg_sender, device.id
+                )));
+            }
+
+            let rows: Vec<_> = db
+                .get_analytics(&device.id, &publish.metric_type)?
+                .into_iter()
+                .filter(|row| publish.time_window.as_ref().is_none_or(|window| &row.time_window == window))
+                .collect();
+            if rows.is_empty() {
+                return Err(LCoreError::Policy(format!(
+                    "publish_analytics rejected: no {} analytics for {}",
+                    publish.metric_type, device.id
+                )));
+            }
+
+            for row in &rows {
+                let payload = analytics::notice_payload(&row.device_id, &row.metric_type, &row.time_window, row.value)?;
+                rollup::send_notice(client, server_addr, &payload).await?;
+            }
+            info!("Published {} {} analytics notices for {}", rows.len(), publish.metric_type, device.id);
+            Ok("accept")
+        }
+        "delete_device" => {
+            let delete: DeletePayload = serde_json::from_value(wrapped.payload)?;
+            let device = match db.get_device(&delete.device_id)? {
+                Some(device) => device,
+                None => {
+                    return Err(LCoreError::Policy(format!(
+                        "delete_device rejected: device {} is not registered",
+                        delete.device_id
+                    )));
+                }
+            };
+            if !permissions.can_manage(&metadata.msg_sender, &device) {
+                return Err(LCoreError::Policy(format!(
+                    "delete_device rejected: {} may not delete {}",
+                    metadata.msg_sender, device.id
+                )));
+            }
+            // The stake stays slashable until it has unbonded; `release_stake` returns it
+            if let Some(staking) = &policies.staking {
+                if let Some(until) = staking.unbond(db, &device.id, &metadata)? {
+                    info!("Stake of device {} unbonding until {}", device.id, until);
+                }
+            }
+            db.delete_device(&device.id)?;
+            info!("Device {} deleted", device.id);
+            Ok("accept")
+        }
+        "withdraw" => {
+            let withdraw: WithdrawPayload = serde_json::from_value(wrapped.payload)?;
+            let asset = match portals::Asset::parse(&withdraw.asset) {
+                Ok(asset) => asset,
+                Err(e) => {
+                    return Err(LCoreError::Policy(format!("withdraw rejected: {}", e)));
+                }
+            };
+            let amount = match withdraw.amount.parse::<u128>() {
+                Ok(amount) if amount > 0 => amount,
+                _ => {
+                    return Err(LCoreError::Policy("withdraw rejected: amount must be a positive integer".to_string()));
+                }
+            };
+            let voucher = match &asset {
+                portals::Asset::Erc20(token) => Voucher::erc20_transfer(token, &metadata.msg_sender, amount)?,
+                portals::Asset::Ether => match db.get_dapp_address()? {
+                    Some(dapp_address) => Voucher::ether_withdrawal(&dapp_address, &metadata.msg_sender, amount)?,
+                    None => {
+                        return Err(LCoreError::Policy("withdraw rejected: dApp address has not been relayed yet".to_string()));
+                    }
+                },
+            };
+            if !db.debit_balance(&metadata.msg_sender, &asset, amount)? {
+                return Err(LCoreError::Policy(format!("withdraw rejected: insufficient {} balance", asset.as_str())));
+            }
+            db.insert_voucher(&voucher, &format!("withdraw:{}", metadata.msg_sender), &metadata)?;
+            rollup::send_voucher(client, server_addr, &voucher).await?;
+            info!("{} withdrew {} {}", metadata.msg_sender, amount, asset.as_str());
+            Ok("accept")
+        }
+        "release_stake" => {
+            let release: ReleaseStakePayload = serde_json::from_value(wrapped.payload)?;
+            let staking = match &policies.staking {
+                Some(staking) => staking,
+                None => {
+                    return Err(LCoreError::Policy("release_stake rejected: staking is disabled".to_string()));
+                }
+            };
+            if db.get_stake(&release.device_id)?.is_some_and(|stake| stake.owner != metadata.msg_sender) {
+                return Err(LCoreError::Policy(format!(
+                    "release_stake rejected: {} does not own the stake of {}",
+                    metadata.msg_sender, release.device_id
+                )));
+            }
+            let released = staking
+                .release(db, &release.device_id, &metadata)?
+                .map_err(|reason| LCoreError::Policy(format!("release_stake rejected: {}", reason)))?;
+            info!("Released {} stake of device {} to {}", released, release.device_id, metadata.msg_sender);
+            Ok("accept")
+        }
+        "slash" => {
+            let slash_pl: SlashPayload = serde_json::from_value(wrapped.payload)?;
+            let staking = match &policies.staking {
+                Some(staking) => staking,
+                None => {
+                    return Err(LCoreError::Policy("slash rejected: staking is disabled".to_string()));
+                }
+            };
+            if !permissions.is_admin(&metadata.msg_sender) {
+                return Err(LCoreError::Policy(format!("slash rejected: {} is not an admin", metadata.msg_sender)));
+            }
+            let amount = match slash_pl.amount.as_deref().map(str::parse::<u128>).transpose() {
+                Ok(amount) => amount,
+                Err(e) => {
+                    return Err(LCoreError::Policy(format!("slash rejected: invalid amount: {}", e)));
+                }
+            };
+            let amount = staking.slash(db, &slash_pl.device_id, amount, &slash_pl.reason, &metadata)?;
+            if amount == 0 {
+                return Err(LCoreError::Policy(format!(
+                    "slash rejected: device {} has no stake left",
+                    slash_pl.device_id
+                )));
+            }
+            send_slash_notice(client, server_addr, &SlashEvent {
+                device_id: slash_pl.device_id,
+                amount,
+                reason: slash_pl.reason,
+            }).await?;
+            Ok("accept")
+        }
+        _ => {
+            Err(LCoreError::InvalidInput(format!("Unknown action {}", wrapped.action)))
+        }
+    }
+}
+
+/// Authenticates, encrypts and stores one reading.
+/// `signature_verified` is set when a batch root signature already covers the reading.
+pub(crate) fn process_reading(
+    db: &Database,
+    reading: &DataPayload,
+    metadata: &AdvanceMetadata,
+    signature_verified: bool,
+    policies: &Policies,
+) -> Result<ReadingOutcome, LCoreError> {
+    // --- Device Authentication ---
+    let data_bytes = match hex::decode(&reading.data) {
+        Ok(bytes) => bytes,
+        Err(e) => return Ok(ReadingOutcome::Rejected(format!("Invalid hex data: {}", e))),
+    };
+
+    let device_did = reading.device_id.as_str();
+
+    // --- Gateway binding: the L1 sender must be one the device declared ---
+    if let Some(senders) = db.get_authorized_senders(device_did)? {
+        if !senders.contains(&metadata.msg_sender) {
+            return Ok(ReadingOutcome::Rejected(format!(
+                "Sender {} is not authorized to submit for device {}",
+                metadata.msg_sender, device_did
+            )));
+        }
+    }
+
+    let device = match db.get_device(device_did)? {
+        Some(device) => device,
+        None => return Ok(ReadingOutcome::Rejected(format!("Device {} is not registered", device_did))),
+    };
+    if device.status != DeviceStatus::Active {
+        return Ok(ReadingOutcome::Rejected(format!(
+            "Device {} is {}",
+            device_did,
+            device.status.as_str()
+        )));
+    }
+
+    let mut authenticated = signature_verified;
+    if signature_verified {
+        debug!("Reading for device {} covered by batch root signature", device_did);
+    } else if let Some(pk_json) = db.get_device_public_key(device_did)? {
+        if !reading.jws.is_empty() {
+            // Signed for the counter this reading will be stored under, so it cannot be replayed
+            let expected_counter = db.get_message_counter(device_did)? + 1;
+            let message = device_auth::reading_message(device_did, expected_counter, &data_bytes);
+            match device_auth::verify_device_signature(&reading.jws, &message, &pk_json) {
+                Ok(_) => {
+                    authenticated = true;
+                    debug!("Device signature verified successfully!");
+                }
+                Err(e) => {
+                    METRICS.record_signature_failure("reading");
+                    return Ok(ReadingOutcome::Rejected(format!(
+                        "Device authentication failed for counter {}: {}",
+                        expected_counter, e
+                    )));
+                }
+            }
+        } else {
+            debug!("No JWS provided; skipping signature verification for device {}", device_did);
+        }
+    } else {
+        debug!("No public key on record for device {}; skipping verification", device_did);
+    }
+
+    // --- Schema validation, before anything is encrypted or stored ---
+    if let Some(device_type) = device.device_type.as_deref() {
+        if let Some(schema_json) = db.get_device_schema(device_type)? {
+            if let Err(errors) = schema::validate_reading(&schema_json, &data_bytes) {
+                return Ok(ReadingOutcome::Rejected(format!(
+                    "Reading does not match {} schema: {}",
+                    device_type,
+                    errors.join("; ")
+                )));
+            }
+        }
+    }
+
+    if policies.require_device_signature && !authenticated {
+        return Ok(ReadingOutcome::Rejected(format!("Reading for device {} is not signed by a registered key", device_did)));
+    }
+
+    // --- Dual Encryption with deterministic nonce ---
+    let encryption_context = policies.encryption_context.as_str();
+
+    // Obtain per-device counter (incremented atomically)
+    let counter = db.next_message_counter(device_did)?;
+
+    // Stage 1
+    let started = Instant::now();
+    let key1 = Stage1Encryption::derive_key_from_did(device_did)?;
+    let stage1 = Stage1Encryption::new(key1);
+    let nonce1 = derive_stage1_nonce(device_did, counter);
+    let ciphertext1 = stage1.encrypt_with_nonce(&data_bytes, &nonce1)?;
+    debug!("Stage 1 (AES) ciphertext length: {}", ciphertext1.len());
+
+    // Stage 2
+    let key2 = Stage2Encryption::derive_key_from_context(encryption_context)?;
+    let stage2 = Stage2Encryption::new(key2);
+    let nonce2 = derive_stage2_nonce(device_did, counter);
+    let ciphertext2 = stage2.encrypt_with_nonce(&ciphertext1, &nonce2)?;
+    METRICS.observe_encrypt(started.elapsed());
+    debug!("Stage 2 (ChaCha) ciphertext length: {}", ciphertext2.len());
+
+    // --- Database Operations ---
+    // Store the encrypted payload
+    let key1_hash = hex::encode(Sha256::digest(key1));
+    let key2_hash = hex::encode(Sha256::digest(key2));
+
+    db.insert_sensor_data(
+        device_did,
+        &ciphertext2,
+        &key1_hash,
+        &key2_hash,
+        counter,
+        metadata,
+    )?;
+
+    // --- In-VM analytics over the accepted plaintext ---
+    if policies.features.analytics {
+        let max_fields = policies.features.analytics_max_fields;
+        let aggregates = analytics::record_reading(db, device_did, &data_bytes, max_fields, metadata)?;
+        if aggregates > 0 {
+            info!("Updated {} analytics aggregates for device {}", aggregates, device_did);
+        }
+    }
+
+    let mut outputs = ReadingOutputs::default();
+
+    // --- Threshold rules ---
+    if policies.features.rules {
+        outputs.alerts = rules::evaluate(db, &device, &data_bytes, metadata)?;
+    }
+
+    // --- Slashing rules, only for readings the device signed ---
+    if let Some(staking) = policies.staking.as_ref().filter(|_| authenticated) {
+        for alert in outputs.alerts.iter().filter(|alert| alert.state == rules::AlertState::Triggered.as_str()) {
+            let slash_amount = db.get_rule(alert.rule_id)?.and_then(|rule| rule.slash_amount);
+            if let Some(slash_amount) = slash_amount {
+                // Slash notices are public, so the reason names the rule but not the reading
+                let reason = format!("rule #{} triggered", alert.rule_id);
+                let amount = staking.slash(db, &device.id, Some(slash_amount), &reason, metadata)?;
+                if amount > 0 {
+                    outputs.slashes.push(SlashEvent { device_id: device.id.clone(), amount, reason });
+                }
+            }
+        }
+    }
+
+    // --- Contribution rewards, only for readings the device signed ---
+    if let Some(rewards) = policies.rewards.as_ref().filter(|_| authenticated) {
+        outputs.vouchers.extend(rewards.on_reading(db, &device, metadata)?);
+    }
+
+    Ok(ReadingOutcome::Accepted(outputs))
+}
+
+async fn send_slash_notice(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    slash: &SlashEvent,
+) -> Result<(), LCoreError> {
+    let notice = object! {
+        "type" => "slash",
+        "device_id" => slash.device_id.as_str(),
+        "amount" => slash.amount.to_string(),
+        "reason" => slash.reason.as_str(),
+    };
+    rollup::send_notice(client, server_addr, notice.dump().as_bytes()).await?;
+    info!("Slashed {} from device {}: {}", slash.amount, slash.device_id, slash.reason);
+    Ok(())
+}
+
+/// Credits a portal deposit to the depositor's internal balance
+async fn handle_deposit(
+    db: &Database,
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    kind: PortalKind,
+    payload: &[u8],
+    metadata: &AdvanceMetadata,
+) -> Result<&'static str, LCoreError> {
+    let deposit = match portals::decode_deposit(kind, payload) {
+        Ok(Some(deposit)) => deposit,
+        Ok(None) => {
+            warn!("Ignoring {:?} deposit whose token transfer failed", kind);
+            return Ok("accept");
+        }
+        Err(e) => {
+            return Err(LCoreError::InvalidInput(format!("Rejecting malformed {:?} deposit: {}", kind, e)));
+        }
+    };
+
+    // The portal has already moved the funds, so a deposit the ledger cannot hold is kept unclaimed
+    let mut report = object! {
+        "type" => "deposit",
+        "asset" => deposit.asset.as_str(),
+        "depositor" => deposit.depositor.as_str(),
+        "amount" => deposit.amount.to_string(),
+    };
+    match db.record_deposit(&deposit, metadata)? {
+        Some(balance) => {
+            report["balance"] = balance.to_string().into();
+            info!("Credited {} {} to {}", deposit.amount, deposit.asset.as_str(), deposit.depositor);
+        }
+        None => {
+            report["unclaimed"] = true.into();
+            warn!("Holding {} {} from {} unclaimed: the ledger cannot credit it", deposit.amount, deposit.asset.as_str(), deposit.depositor);
+        }
+    }
+    rollup::send_report(client, server_addr, report.dump().as_bytes()).await?;
+    Ok("accept")
+}
+
+/// Device rules may be managed by the device owner or an admin; device-type rules by admins only.
+fn can_manage_rule(
+    db: &Database,
+    permissions: &Permissions,
+    sender: &str,
+    rule: &RuleRow,
+) -> Result<bool, LCoreError> {
+    match &rule.device_id {
+        Some(device_id) => Ok(match db.get_device(device_id)? {
+            Some(device) => permissions.can_manage(sender, &device),
+            None => false,
+        }),
+        None => Ok(permissions.is_admin(sender)),
+    }
+}
+
+/// Emit one notice per alert transition and every reward voucher. Notices are
+/// public, so alert notices carry the rule and state but not the reading.
+async fn send_reading_outputs(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    outputs: &ReadingOutputs,
+) -> Result<(), LCoreError> {
+    for alert in &outputs.alerts {
+        let notice = serde_json::json!({
+            "type": "alert",
+            "alert": {
+                "rule_id": alert.rule_id,
+                "device_id": alert.device_id,
+                "state": alert.state,
+                "input_index": alert.input_index,
+            },
+        });
+        rollup::send_notice(client, server_addr, notice.to_string().as_bytes()).await?;
+        info!("Alert {} for device {}: {} = {}", alert.state, alert.device_id, alert.field, redacted(alert.value));
+    }
+    for slash in &outputs.slashes {
+        send_slash_notice(client, server_addr, slash).await?;
+    }
+    for voucher in &outputs.vouchers {
+        rollup::send_voucher(client, server_addr, voucher).await?;
+        info!("Voucher emitted to {}", voucher.destination);
+    }
+    Ok(())
+}
+
+/// Processes a gateway batch all-or-nothing: if any reading is rejected the
+/// whole batch is rolled back. Per-item results are always sent as a report.
+async fn handle_batch(
+    db: &Database,
+    policies: &Policies,
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    batch_pl: BatchPayload,
+    metadata: &AdvanceMetadata,
+) -> Result<&'static str, LCoreError> {
+    if batch_pl.items.is_empty() || batch_pl.items.len() > batch::MAX_BATCH_SIZE {
+        return Err(LCoreError::Policy(format!(
+            "submit_batch rejected: batch must hold 1..={} items, got {}",
+            batch::MAX_BATCH_SIZE, batch_pl.items.len()
+        )));
+    }
+
+    let root_verified = match &batch_pl.root_jws {
+        Some(root_jws) => match verify_batch_root(db, &batch_pl.items, root_jws)? {
+            Ok(()) => true,
+            Err(reason) => {
+                warn!("submit_batch rejected: {}", redacted(&reason));
+                let report = object! {
+                    "type" => "batch_result",
+                    "accepted" => false,
+                    "error" => reason,
+                };
+                rollup::send_report(client, server_addr, report.dump().as_bytes()).await?;
+                return Ok("reject");
+            }
+        },
+        None => false,
+    };
+
+    let savepoint = db.savepoint()?;
+    let mut results = JsonValue::new_array();
+    let mut outputs = ReadingOutputs::default();
+    let mut all_accepted = true;
+    for (index, item) in batch_pl.items.iter().enumerate() {
+        let result = match process_reading(db, item, metadata, root_verified, policies)? {
+            ReadingOutcome::Accepted(item_outputs) => {
+                outputs.extend(item_outputs);
+                object! {
+                    "index" => index,
+                    "device_id" => item.device_id.as_str(),
+                    "status" => "accepted",
+                }
+            }
+            ReadingOutcome::Rejected(reason) => {
+                all_accepted = false;
+                object! {
+                    "index" => index,
+                    "device_id" => item.device_id.as_str(),
+                    "status" => "rejected",
+                    "reason" => reason,
+                }
+            }
+        };
+        results.push(result).map_err(|e| LCoreError::Internal(e.to_string()))?;
+    }
+
+    if all_accepted {
+        savepoint.commit()?;
+        send_reading_outputs(client, server_addr, &outputs).await?;
+    } else {
+        drop(savepoint);
+    }
+
+    let report = object! {
+        "type" => "batch_result",
+        "accepted" => all_accepted,
+        "items" => results,
+    };
+    rollup::send_report(client, server_addr, report.dump().as_bytes()).await?;
+
+    info!(
+        "submit_batch of {} readings {}",
+        batch_pl.items.len(),
+        if all_accepted { "accepted" } else { "rejected" }
+    );
+    Ok(if all_accepted { "accept" } else { "reject" })
+}
+
+/// Checks a batch root signature. Root-signed batches must belong to a single
+/// registered device, whose current key signs `device_auth::batch_message`.
+fn verify_batch_root(
+    db: &Database,
+    items: &[DataPayload],
+    root_jws: &str,
+) -> Result<Result<(), String>, LCoreError> {
+    let device_id = items[0].device_id.as_str();
+    if items.iter().any(|item| item.device_id != device_id) {
+        return Ok(Err("root-signed batches must contain a single device".to_string()));
+    }
+
+    // Items are stored under consecutive counters after the device's current one
+    let first_counter = db.get_message_counter(device_id)? + 1;
+    let mut leaves = Vec::with_capacity(items.len());
+    for (counter, item) in (first_counter..).zip(items) {
+        match hex::decode(&item.data) {
+            Ok(data) => leaves.push(batch::reading_leaf(device_id, counter, &data)),
+            Err(e) => return Ok(Err(format!("Invalid hex data: {}", e))),
+        }
+    }
+    let root = batch::merkle_root(&leaves).ok_or_else(|| LCoreError::InvalidInput("Empty batch".to_string()))?;
+
+    let pk_json = match db.get_device_public_key(device_id)? {
+        Some(pk_json) => pk_json,
+        None => return Ok(Err(format!("device {} is not registered", device_id))),
+    };
+    let message = device_auth::batch_message(device_id, leaves.len(), &root);
+    match device_auth::verify_device_signature(root_jws, message.as_bytes(), &pk_json) {
+        Ok(()) => Ok(Ok(())),
+        Err(e) => {
+            METRICS.record_signature_failure("batch");
+            Ok(Err(format!("batch root authentication failed: {}", e)))
+        }
+    }
+}
+
+/// Applies a device lifecycle action once it has been signed by the device's current key,
+/// or sent by the device owner or a dApp admin.
+fn handle_lifecycle(
+    db: &Database,
+    permissions: &Permissions,
+    action: &str,
+    payload: LifecyclePayload,
+    metadata: &AdvanceMetadata,
+) -> Result<&'static str, LCoreError> {
+    let device = match db.get_device(&payload.device_id)? {
+        Some(device) => device,
+        None => {
+            return Err(LCoreError::Policy(format!(
+                "{} rejected: device {} is not registered",
+                action, payload.device_id
+            )));
+        }
+    };
+
+    let required_status = match action {
+        "reactivate" => DeviceStatus::Deactivated,
+        _ => DeviceStatus::Active,
+    };
+    if device.status != required_status {
+        return Err(LCoreError::Policy(format!(
+            "{} rejected: device {} is {}",
+            action, device.id, device.status.as_str()
+        )));
+    }
+
+    let value = match action {
+        "update_did" => payload.did_document.as_deref(),
+        "rotate_key" => payload.public_key.as_deref(),
+        _ => Some(""),
+    };
+    let value = match value {
+        Some(value) => value,
+        None => {
+            return Err(LCoreError::Policy(format!("{} rejected: missing new value for device {}", action, device.id)));
+        }
+    };
+
+    if permissions.can_manage(&metadata.msg_sender, &device) {
+        info!("{} on {} authorized for {}", action, device.id, metadata.msg_sender);
+    } else {
+        let message = device_auth::lifecycle_message(action, &device.id, device.lifecycle_nonce, value);
+        if let Err(e) = device_auth::verify_device_signature(&payload.jws, message.as_bytes(), &device.public_key) {
+            METRICS.record_signature_failure("lifecycle");
+            return Err(LCoreError::DeviceAuth(format!("{} rejected: device authentication failed: {}", action, e)));
+        }
+    }
+
+    match action {
+        "update_did" => db.update_did_document(&device.id, value)?,
+        "rotate_key" => {
+            if let Err(e) = device_auth::validate_public_jwk(value) {
+                return Err(LCoreError::Policy(format!("rotate_key rejected: {}", e)));
+            }
+            let key_id = db.rotate_device_key(&device.id, value, metadata)?;
+            info!("Device {} now signs with key #{}", device.id, key_id);
+        }
+        "deactivate" => db.set_device_status(&device.id, DeviceStatus::Deactivated)?,
+        "reactivate" => db.set_device_status(&device.id, DeviceStatus::Active)?,
+        _ => unreachable!("lifecycle actions are matched in handle_advance"),
+    }
+
+    info!("Device {}: {} applied", device.id, action);
+    Ok("accept")
+}
+
+async fn handle_inspect(
+    db: &Database,
+    policies: &Policies,
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    request: JsonValue,
+) -> Result<&'static str, LCoreError> {
+    let payload_str = request["data"]["payload"]
+        .as_str()
+        .ok_or_else(|| LCoreError::Protocol("Missing payload".to_string()))?;
+    let payload_bytes = hex::decode(payload_str.trim_start_matches("0x"))?;
+    let query_str = std::str::from_utf8(&payload_bytes)?;
+    
+    // The query string carries the reader's JWS, so only its kind and subject go out in the clear
+    let (kind, rest) = query_str.split_once(':').unwrap_or((query_str, ""));
+    let subject = rest.split('?').next().unwrap_or_default();
+    info!(kind, subject, query = %redacted(query_str), "Received inspect request");
+
+    // Simple query format: "get_latest:<device_id>?consumer=<address>&jws=<signature>"
+    if let Some(latest_query) = query_str.strip_prefix("get_latest:") {
+        let query = ReadQuery::parse(latest_query)?;
+        if let Some(sensor_row) = db.get_latest_sensor_data(query.device_id)? {
+            if let Some(reason) = check_reader(db, &policies.permissions, &query, &sensor_row)? {
+                send_access_denied(client, server_addr, &query, &reason, sensor_row.counter).await?;
+                return Ok("reject");
+            }
+
+            let sensor_data = decrypt_reading(sensor_row, &policies.encryption_context)?;
+            let report = object! {
+                "type" => "decrypted_sensor_data",
+                "data" => serde_json::to_string(&sensor_data)?
+            };
+            
+            debug!(report = %redacted(report.dump()), "Sending report with decrypted data");
+            rollup::send_report(client, server_addr, report.dump().as_bytes()).await?;
+        } else {
+             info!("No data found for device {}", query.device_id);
+        }
+    } else if let Some(alerts_query) = query_str.strip_prefix("get_alerts:") {
+        // Alerts carry the values that triggered them, so they are read under the latest reading's check
+        let query = ReadQuery::parse(alerts_query)?;
+        if let Some(sensor_row) = db.get_latest_sensor_data(query.device_id)? {
+            if let Some(reason) = check_reader(db, &policies.permissions, &query, &sensor_row)? {
+                send_access_denied(client, server_addr, &query, &reason, sensor_row.counter).await?;
+                return Ok("reject");
+            }
+        }
+        let alerts = db.get_alerts(query.device_id)?;
+        let report = serde_json::json!({ "type": "alerts", "device_id": query.device_id, "alerts": alerts });
+        rollup::send_report(client, server_addr, report.to_string().as_bytes()).await?;
+        info!("Reported {} alerts for device {}", alerts.len(), query.device_id);
+    } else if let Some(address_query) = query_str.strip_prefix("get_balance:") {
+        let address = normalize_address(address_query)?;
+        let mut balances = JsonValue::new_object();
+        for (asset, amount) in db.get_balances(&address)? {
+            balances[asset.as_str()] = amount.to_string().into();
+        }
+        let mut unclaimed = JsonValue::new_array();
+        for (asset, amount) in db.get_unclaimed_deposits(&address)? {
+            unclaimed
+                .push(object! { "asset" => asset.as_str(), "amount" => amount })
+                .map_err(|e| LCoreError::Internal(e.to_string()))?;
+        }
+        let report = object! {
+            "type" => "balances",
+            "address" => address.as_str(),
+            "balances" => balances,
+            "unclaimed" => unclaimed,
+        };
+        rollup::send_report(client, server_addr, report.dump().as_bytes()).await?;
+    } else {
+        return Err(LCoreError::InvalidInput(
+            "Invalid inspect query format. Use 'get_latest:<device_id>?consumer=<address>&jws=<signature>', 'get_alerts:<device_id>?consumer=<address>&jws=<signature>' or 'get_balance:<address>'".to_string(),
+        ));
+    }
+    
+    Ok("accept")
+}
+
+/// Reverses both encryption stages of a stored reading
+pub(crate) fn decrypt_reading(
+    sensor_row: database::SensorDataRow,
+    encryption_context: &str,
+) -> Result<DecryptedSensorData, LCoreError> {
+    let counter = sensor_row.counter;
+
+    // Stage 2 Decryption (ChaCha20)
+    let started = Instant::now();
+    let key2 = Stage2Encryption::derive_key_from_context(encryption_context)?;
+    let stage2 = Stage2Encryption::new(key2);
+    let nonce2 = derive_stage2_nonce(&sensor_row.device_id, counter);
+    let ciphertext1 = stage2.decrypt_with_nonce(&sensor_row.encrypted_payload, &nonce2)?;
+    debug!("Stage 2 decryption successful.");
+
+    // Stage 1 Decryption (AES)
+    let key1 = Stage1Encryption::derive_key_from_did(&sensor_row.device_id)?;
+    let stage1 = Stage1Encryption::new(key1);
+    let nonce1 = derive_stage1_nonce(&sensor_row.device_id, counter);
+    let plaintext_bytes = stage1.decrypt_with_nonce(&ciphertext1, &nonce1)?;
+    METRICS.observe_decrypt(started.elapsed());
+    debug!("Stage 1 decryption successful.");
+
+    Ok(DecryptedSensorData {
+        id: sensor_row.id,
+        device_id: sensor_row.device_id,
+        decrypted_payload: String::from_utf8(plaintext_bytes)?,
+        timestamp: sensor_row.timestamp,
+    })
+}
+
+/// A device data query: `<device_id>?consumer=<address>&jws=<signature>`
+struct ReadQuery<'a> {
+    device_id: &'a str,
+    consumer: Option<String>,
+    /// Compact JWS over `device_auth::read_message` by the consumer's reader key
+    jws: Option<&'a str>,
+}
+
+impl<'a> ReadQuery<'a> {
+    fn parse(query: &'a str) -> Result<Self, LCoreError> {
+        let (device_id, params) = query.split_once('?').unwrap_or((query, ""));
+        let mut parsed = ReadQuery { device_id, consumer: None, jws: None };
+        for param in params.split('&').filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some(("consumer", consumer)) => parsed.consumer = Some(normalize_address(consumer)?),
+                Some(("jws", jws)) => parsed.jws = Some(jws),
+                _ => return Err(LCoreError::InvalidInput(format!("Unknown query parameter {}", param))),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Refuse a read query, handing back the message the consumer's reader key must sign
+async fn send_access_denied(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    query: &ReadQuery<'_>,
+    reason: &str,
+    counter: u64,
+) -> Result<(), LCoreError> {
+    warn!("Access to device {} denied for {:?}: {}", query.device_id, query.consumer, reason);
+    let report = object! {
+        "type" => "access_denied",
+        "device_id" => query.device_id,
+        "reason" => reason,
+        "challenge" => device_auth::read_message(query.device_id, counter),
+    };
+    rollup::send_report(client, server_addr, report.dump().as_bytes()).await
+}
+
+/// Why the query's consumer may not read `reading`, or `None` if they may.
+///
+/// Inspect calls carry no authenticated sender, so the consumer must prove the
+/// address with a signature by the reader key it registered through
+/// `set_reader_key`. Owners and admins then read any reading; other consumers
+/// need a grant covering it.
+fn check_reader(
+    db: &Database,
+    permissions: &Permissions,
+    query: &ReadQuery,
+    reading: &database::SensorDataRow,
+) -> Result<Option<String>, LCoreError> {
+    let (consumer, jws) = match (&query.consumer, query.jws) {
+        (Some(consumer), Some(jws)) => (consumer.as_str(), jws),
+        _ => return Ok(Some("query must name a consumer and carry its signature".to_string())),
+    };
+    let reader_key = match db.get_reader_key(consumer)? {
+        Some(reader_key) => reader_key,
+        None => return Ok(Some(format!("{} has not registered a reader key", consumer))),
+    };
+    let message = device_auth::read_message(&reading.device_id, reading.counter);
+    if let Err(e) = device_auth::verify_device_signature(jws, message.as_bytes(), &reader_key) {
+        METRICS.record_signature_failure("read");
+        return Ok(Some(format!("consumer authentication failed: {}", e)));
+    }
+
+    let device = match db.get_device(&reading.device_id)? {
+        Some(device) => device,
+        None => return Ok(Some(format!("device {} is not registered", reading.device_id))),
+    };
+    if permissions.can_manage(consumer, &device) {
+        return Ok(None);
+    }
+    let mut covered = false;
+    for grant in db.get_access_grants(&device.id, consumer)? {
+        covered |= grant.covers(reading)?;
+    }
+    Ok((!covered).then(|| format!("{} holds no grant covering this reading", consumer)))
+}
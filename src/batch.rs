@@ -0,0 +1,93 @@
+// lcore-node/src/batch.rs
+//
+// Merkle commitments for gateway batch submissions
+
+use sha2::{Digest, Sha256};
+
+/// Upper bound on readings per `submit_batch` input, keeping one input's work bounded.
+pub const MAX_BATCH_SIZE: usize = 512;
+
+/// Leaf hash of one reading: `SHA-256(device_id || 0x00 || counter || data)`,
+/// with `counter` as 8 big-endian bytes.
+///
+/// Binding the device id into the leaf stops a root signed for one device's
+/// readings from being replayed against another device, and the message
+/// counter each reading will be stored under stops it being replayed at all.
+pub fn reading_leaf(device_id: &str, counter: u64, data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(device_id.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(counter.to_be_bytes());
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// Domain prefixes keeping leaf and interior node hashes apart
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Merkle root over leaves: each leaf is hashed as `SHA-256(0x00 || leaf)`,
+/// then nodes are paired as `SHA-256(0x01 || left || right)` level by level,
+/// promoting an odd node at the end of a level unchanged.
+///
+/// The prefixes stop a list of interior nodes from posing as leaves. The root
+/// alone does not fix the number of leaves, so signers commit to it
+/// separately (see `device_auth::batch_message`). Returns `None` for an empty
+/// list.
+pub fn merkle_root(leaves: &[[u8; 32]]) -> Option<[u8; 32]> {
+    if leaves.is_empty() {
+        return None;
+    }
+
+    let mut level: Vec<[u8; 32]> = leaves
+        .iter()
+        .map(|leaf| Sha256::new().chain_update([LEAF_PREFIX]).chain_update(leaf).finalize().into())
+        .collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.update([NODE_PREFIX]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize().into()
+                }
+                [single] => *single,
+                _ => unreachable!("chunks(2) yields one or two nodes"),
+            })
+            .collect();
+    }
+    Some(level[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merkle_root() {
+        let a = reading_leaf("did:example:1", 1, b"21.5");
+        let b = reading_leaf("did:example:1", 2, b"21.7");
+        let c = reading_leaf("did:example:1", 3, b"21.9");
+
+        let leaf = |leaf: [u8; 32]| -> [u8; 32] { Sha256::new().chain_update([0x00]).chain_update(leaf).finalize().into() };
+        let node = |left: [u8; 32], right: [u8; 32]| -> [u8; 32] {
+            Sha256::new().chain_update([0x01]).chain_update(left).chain_update(right).finalize().into()
+        };
+
+        assert_eq!(merkle_root(&[]), None);
+        assert_eq!(merkle_root(&[a]), Some(leaf(a)));
+
+        let ab = node(leaf(a), leaf(b));
+        assert_eq!(merkle_root(&[a, b, c]), Some(node(ab, leaf(c))));
+
+        // Interior nodes cannot be passed off as leaves
+        assert_ne!(merkle_root(&[ab, leaf(c)]), merkle_root(&[a, b, c]));
+
+        // Order matters, and so does the counter a reading is signed for
+        assert_ne!(merkle_root(&[b, a]), merkle_root(&[a, b]));
+        assert_ne!(reading_leaf("did:example:1", 2, b"21.5"), a);
+    }
+}
@@ -0,0 +1,122 @@
+// lcore-node/src/bin/replay.rs
+//
+// Replay logged inputs against a fresh database and print every output and
+// the final state hash, to reproduce disputes and production bugs locally.
+//
+//     replay [--config FILE] [--dapp ADDRESS] [--database PATH] LOG...
+
+use dapp::app::{App, Policies};
+use dapp::config::Config;
+use dapp::database::Database;
+use dapp::error::LCoreError;
+use dapp::logging;
+use dapp::replay;
+use dapp::rollup_stub::{Output, Processed, RollupStub};
+use std::env;
+use std::path::Path;
+
+const USAGE: &str = "usage: replay [--config FILE] [--dapp ADDRESS] [--database PATH] LOG...";
+
+struct Args {
+    config: Option<String>,
+    dapp: Option<String>,
+    database: Option<String>,
+    logs: Vec<String>,
+}
+
+fn parse_args() -> Result<Args, LCoreError> {
+    let mut args = Args { config: None, dapp: None, database: None, logs: Vec::new() };
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let slot = match arg.as_str() {
+            "--config" => &mut args.config,
+            "--dapp" => &mut args.dapp,
+            "--database" => &mut args.database,
+            "-h" | "--help" => return Err(LCoreError::Config(USAGE.to_string())),
+            _ if arg.starts_with('-') => return Err(LCoreError::Config(format!("unknown option {}\n{}", arg, USAGE))),
+            _ => {
+                args.logs.push(arg);
+                continue;
+            }
+        };
+        *slot = Some(argv.next().ok_or_else(|| LCoreError::Config(format!("{} needs a value\n{}", arg, USAGE)))?);
+    }
+    if args.logs.is_empty() {
+        return Err(LCoreError::Config(USAGE.to_string()));
+    }
+    Ok(args)
+}
+
+/// The deployment's genesis settings; `--config` wins over `LCORE_CONFIG_FILE`.
+fn load_config(path: Option<&str>) -> Result<Config, LCoreError> {
+    match path {
+        Some(path) => {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| LCoreError::Config(format!("Cannot read {}: {}", path, e)))?;
+            Config::from_sources(Some(&contents), |name| env::var(name).ok())
+        }
+        None => Config::load(),
+    }
+}
+
+/// Text outputs are printed as they are, anything else as hex.
+fn display(payload: &[u8]) -> String {
+    match std::str::from_utf8(payload) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("0x{}", hex::encode(payload)),
+    }
+}
+
+fn print_processed(position: usize, processed: &Processed) {
+    let request = &processed.request;
+    match request["data"]["metadata"]["input_index"].as_u64() {
+        Some(input_index) => println!("#{} advance input {} {}", position, input_index, processed.status),
+        None => println!("#{} {} {}", position, request["request_type"], processed.status),
+    }
+    for output in &processed.outputs {
+        match output {
+            Output::Notice(payload) => println!("  notice {}", display(payload)),
+            Output::Report(payload) => println!("  report {}", display(payload)),
+            Output::Voucher { destination, payload } => println!("  voucher {} 0x{}", destination, hex::encode(payload)),
+            Output::Exception(payload) => println!("  exception {}", display(payload)),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = parse_args()?;
+    let config = load_config(args.config.as_deref())?;
+    logging::init_stderr(&config.logging)?;
+    let policies = Policies::from_config(&config)?;
+
+    let mut requests = Vec::new();
+    for log in &args.logs {
+        let contents = std::fs::read_to_string(log).map_err(|e| LCoreError::Config(format!("Cannot read {}: {}", log, e)))?;
+        requests.extend(replay::parse_log(&contents, args.dapp.as_deref()).map_err(|e| format!("{}: {}", log, e))?);
+    }
+
+    // Replays must start from genesis, so an existing database is never reused
+    let database = args.database.as_deref().unwrap_or(":memory:");
+    if database != ":memory:" && Path::new(database).exists() {
+        return Err(format!("{} already exists; replay needs a fresh database", database).into());
+    }
+
+    let stub = RollupStub::start().await?;
+    for request in &requests {
+        stub.push(request.clone());
+    }
+    let app = App::new(Database::open(database)?, policies, &stub.url());
+    let processed = stub.run(&app).await?;
+
+    for (position, processed) in processed.iter().enumerate() {
+        print_processed(position, processed);
+    }
+    println!("state 0x{}", hex::encode(app.db().state_hash()?));
+
+    if processed.last().is_some_and(|processed| processed.status == "exception") {
+        eprintln!("Replay stopped at an exception; {} requests were not replayed", requests.len() - processed.len());
+        std::process::exit(1);
+    }
+    Ok(())
+}
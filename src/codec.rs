@@ -0,0 +1,107 @@
+// lcore-node/src/codec.rs
+//
+// Input envelope decoding: legacy JSON and compact CBOR
+
+use crate::error::LCoreError;
+use ciborium::value::Value as CborValue;
+use serde_json::Value;
+
+/// Leading byte selecting the CBOR envelope: `0x01 || cbor({"action", "payload"})`.
+///
+/// Byte strings inside the CBOR payload (e.g. `data`) are carried raw instead of
+/// hex-in-JSON, which removes roughly three quarters of the calldata overhead.
+pub const CBOR_ENVELOPE_VERSION: u8 = 0x01;
+
+/// Decode an advance input payload into the `{"action", "payload"}` JSON shape the
+/// handlers consume. Inputs starting with `CBOR_ENVELOPE_VERSION` are CBOR; anything
+/// else is treated as the original UTF-8 JSON envelope.
+pub fn decode_envelope(payload_bytes: &[u8]) -> Result<Value, LCoreError> {
+    match payload_bytes.split_first() {
+        Some((&CBOR_ENVELOPE_VERSION, cbor)) => {
+            let value: CborValue = ciborium::de::from_reader(cbor)
+                .map_err(|e| LCoreError::InvalidInput(format!("Invalid CBOR envelope: {}", e)))?;
+            cbor_to_json(value)
+        }
+        _ => {
+            let json_str = std::str::from_utf8(payload_bytes)?;
+            Ok(serde_json::from_str(json_str)?)
+        }
+    }
+}
+
+/// Convert a CBOR value into JSON. Byte strings become hex strings so the
+/// handlers' existing hex fields (`data`) accept them unchanged.
+fn cbor_to_json(value: CborValue) -> Result<Value, LCoreError> {
+    Ok(match value {
+        CborValue::Null => Value::Null,
+        CborValue::Bool(b) => Value::Bool(b),
+        CborValue::Integer(i) => {
+            let i = i128::from(i);
+            if let Ok(u) = u64::try_from(i) {
+                Value::from(u)
+            } else if let Ok(s) = i64::try_from(i) {
+                Value::from(s)
+            } else {
+                return Err(LCoreError::InvalidInput(format!("CBOR integer out of range: {}", i)));
+            }
+        }
+        CborValue::Float(f) => serde_json::Number::from_f64(f)
+            .map(Value::Number)
+            .ok_or_else(|| LCoreError::InvalidInput("CBOR float is not finite".to_string()))?,
+        CborValue::Bytes(bytes) => Value::String(hex::encode(bytes)),
+        CborValue::Text(text) => Value::String(text),
+        CborValue::Tag(_, inner) => cbor_to_json(*inner)?,
+        CborValue::Array(items) => Value::Array(
+            items.into_iter().map(cbor_to_json).collect::<Result<_, _>>()?,
+        ),
+        CborValue::Map(entries) => {
+            let mut map = serde_json::Map::with_capacity(entries.len());
+            for (key, value) in entries {
+                let key = match key {
+                    CborValue::Text(key) => key,
+                    _ => return Err(LCoreError::InvalidInput("CBOR map keys must be text".to_string())),
+                };
+                map.insert(key, cbor_to_json(value)?);
+            }
+            Value::Object(map)
+        }
+        _ => return Err(LCoreError::InvalidInput("Unsupported CBOR value".to_string())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_envelope_passthrough() {
+        let payload = br#"{"action":"submit","payload":{"device_id":"did:example:1","data":"3231"}}"#;
+        let envelope = decode_envelope(payload).unwrap();
+        assert_eq!(envelope["action"], "submit");
+        assert_eq!(envelope["payload"]["data"], "3231");
+    }
+
+    #[test]
+    fn test_cbor_envelope_matches_json() {
+        let value = CborValue::Map(vec![
+            (CborValue::Text("action".into()), CborValue::Text("submit".into())),
+            (
+                CborValue::Text("payload".into()),
+                CborValue::Map(vec![
+                    (CborValue::Text("device_id".into()), CborValue::Text("did:example:1".into())),
+                    (CborValue::Text("data".into()), CborValue::Bytes(b"21".to_vec())),
+                ]),
+            ),
+        ]);
+        let mut payload = vec![CBOR_ENVELOPE_VERSION];
+        ciborium::ser::into_writer(&value, &mut payload).unwrap();
+
+        let json = br#"{"action":"submit","payload":{"device_id":"did:example:1","data":"3231"}}"#;
+        assert_eq!(decode_envelope(&payload).unwrap(), decode_envelope(json).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_cbor_envelope() {
+        assert!(decode_envelope(&[CBOR_ENVELOPE_VERSION, 0xff]).is_err());
+    }
+}
@@ -1,18 +1,500 @@
+// lcore-node/src/config.rs
+//
+// Node configuration: defaults, overridden by an optional TOML file baked
+// into the machine image, overridden by environment variables
+
+use crate::database::DEFAULT_DB_PATH;
+use crate::error::LCoreError;
+use crate::logging;
+use crate::permissions::Permissions;
+use crate::portals::{Asset, Portals};
+use crate::staking::StakePolicy;
+use crate::vouchers::RewardPolicy;
 use serde::Deserialize;
+use std::env;
+use std::net::SocketAddr;
+
+/// Path of the TOML configuration file; a missing file is only an error when set explicitly.
+pub const CONFIG_FILE_ENV: &str = "LCORE_CONFIG_FILE";
+pub const DEFAULT_CONFIG_FILE: &str = "/etc/lcore-node/config.toml";
+
+/// Context label the stage 2 key is derived from. Changing it makes stored
+/// readings undecryptable, so it must stay fixed for a deployment.
+pub const DEFAULT_ENCRYPTION_CONTEXT: &str = "iot-sensor-data-v1";
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    pub server_addr: String,
-    pub database_url: String,
+    /// `ROLLUP_HTTP_SERVER_URL`
+    pub rollup_http_server_url: String,
+    /// `LCORE_DATABASE_PATH`
+    pub database_path: String,
+    /// `LCORE_HEALTH_BIND_ADDRESS`, e.g. `0.0.0.0:8000`
+    pub health_bind_address: String,
+    /// `LCORE_READY_TIMEOUT_SECS`: how recently the rollup loop must have reached `/finish` to be ready
+    pub ready_timeout_secs: u64,
+    /// `LCORE_ENCRYPTION_CONTEXT`
+    pub encryption_context: String,
+    pub auth: AuthConfig,
+    pub features: FeatureConfig,
+    pub portals: PortalConfig,
+    pub rewards: RewardConfig,
+    pub staking: StakingConfig,
+    pub logging: LoggingConfig,
+    pub dev_api: DevApiConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// `LCORE_ADMIN_ADDRESSES`, comma-separated in the environment
+    pub admin_addresses: Vec<String>,
+    /// `LCORE_REQUIRE_DEVICE_SIGNATURE`: reject readings not signed by a registered device key
+    pub require_device_signature: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureConfig {
+    /// `LCORE_FEATURE_ANALYTICS`: maintain rolling analytics
+    pub analytics: bool,
+    /// `LCORE_ANALYTICS_MAX_FIELDS`: numeric fields of a reading aggregated; the rest are skipped
+    pub analytics_max_fields: usize,
+    /// `LCORE_FEATURE_RULES`: evaluate threshold rules
+    pub rules: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortalConfig {
+    /// `LCORE_ETHER_PORTAL`
+    pub ether_portal: Option<String>,
+    /// `LCORE_ERC20_PORTAL`
+    pub erc20_portal: Option<String>,
+    /// `LCORE_DAPP_ADDRESS_RELAY`
+    pub dapp_address_relay: Option<String>,
+}
+
+/// Rewards are enabled by setting `token`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewardConfig {
+    /// `LCORE_REWARD_TOKEN`
+    pub token: Option<String>,
+    /// `LCORE_REWARD_AMOUNT`, base units as a decimal string
+    pub amount: Option<String>,
+    /// `LCORE_REWARD_EVERY`
+    pub every: u64,
+    /// `LCORE_REWARD_POOL`: ledger account rewards are paid from
+    pub pool: Option<String>,
+}
+
+/// Staking is enabled by a non-zero `amount`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StakingConfig {
+    /// `LCORE_STAKE_AMOUNT`, base units as a decimal string
+    pub amount: Option<String>,
+    /// `LCORE_STAKE_ASSET`: `ether` or an ERC-20 token address
+    pub asset: String,
+    /// `LCORE_TREASURY_ADDRESS`
+    pub treasury: Option<String>,
+    /// `LCORE_STAKE_UNBONDING_SECONDS`: how long a deleted device's stake stays slashable
+    pub unbonding_seconds: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// `LCORE_LOG_LEVEL`: a level or `EnvFilter` directives, e.g. `info,dapp=debug`
+    pub level: String,
+    /// `LCORE_LOG_FORMAT`: `text` or `json`
+    pub format: LogFormat,
+    /// `LCORE_LOG_REDACT`: hide payloads, decrypted data and key material
+    pub redact: bool,
+}
+
+/// Development mode: serve `api::router` instead of talking to a rollups node
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevApiConfig {
+    /// `LCORE_DEV_API`
+    pub enabled: bool,
+    /// `LCORE_DEV_API_BIND_ADDRESS`
+    pub bind_address: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rollup_http_server_url: "http://127.0.0.1:5004".to_string(),
+            database_path: DEFAULT_DB_PATH.to_string(),
+            health_bind_address: "0.0.0.0:8000".to_string(),
+            ready_timeout_secs: 30,
+            encryption_context: DEFAULT_ENCRYPTION_CONTEXT.to_string(),
+            auth: AuthConfig::default(),
+            features: FeatureConfig::default(),
+            portals: PortalConfig::default(),
+            rewards: RewardConfig::default(),
+            staking: StakingConfig::default(),
+            logging: LoggingConfig::default(),
+            dev_api: DevApiConfig::default(),
+        }
+    }
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        Self { analytics: true, analytics_max_fields: 16, rules: true }
+    }
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        Self { token: None, amount: None, every: 1, pool: None }
+    }
+}
+
+impl Default for StakingConfig {
+    fn default() -> Self {
+        Self { amount: None, asset: "ether".to_string(), treasury: None, unbonding_seconds: 604_800 }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self { level: "info".to_string(), format: LogFormat::Text, redact: true }
+    }
+}
+
+impl Default for DevApiConfig {
+    fn default() -> Self {
+        Self { enabled: false, bind_address: "127.0.0.1:8080".to_string() }
+    }
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, config::ConfigError> {
-        // In a real app, you would load from a file and/or environment variables.
-        // For the MVP, we'll hardcode some defaults.
-        Ok(Config {
-            server_addr: "127.0.0.1:3000".to_string(),
-            database_url: "sqlite:lcore.db".to_string(),
-        })
-    }
-} 
\ No newline at end of file
+    /// Load and validate the configuration of this process.
+    pub fn load() -> Result<Self, LCoreError> {
+        let (path, explicit) = match env::var(CONFIG_FILE_ENV) {
+            Ok(path) if !path.trim().is_empty() => (path.trim().to_string(), true),
+            _ => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+        let file = match std::fs::read_to_string(&path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => None,
+            Err(e) => return Err(LCoreError::Config(format!("Cannot read {}: {}", path, e))),
+        };
+        Self::from_sources(file.as_deref(), |name| env::var(name).ok())
+    }
+
+    /// Build a configuration from TOML `file` contents and an environment
+    /// lookup, then validate it. Empty environment values count as unset.
+    pub fn from_sources(file: Option<&str>, env: impl Fn(&str) -> Option<String>) -> Result<Self, LCoreError> {
+        let mut config: Config = match file {
+            Some(contents) => toml::from_str(contents).map_err(|e| LCoreError::Config(e.to_string()))?,
+            None => Config::default(),
+        };
+
+        let var = |name: &str| env(name).map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+        let flag = |name: &str| -> Result<Option<bool>, LCoreError> {
+            var(name)
+                .map(|value| match value.to_ascii_lowercase().as_str() {
+                    "1" | "true" | "yes" | "on" => Ok(true),
+                    "0" | "false" | "no" | "off" => Ok(false),
+                    _ => Err(LCoreError::Config(format!("{} is not a boolean: {}", name, value))),
+                })
+                .transpose()
+        };
+
+        if let Some(url) = var("ROLLUP_HTTP_SERVER_URL") {
+            config.rollup_http_server_url = url;
+        }
+        if let Some(path) = var("LCORE_DATABASE_PATH") {
+            config.database_path = path;
+        }
+        if let Some(address) = var("LCORE_HEALTH_BIND_ADDRESS") {
+            config.health_bind_address = address;
+        }
+        if let Some(timeout) = var("LCORE_READY_TIMEOUT_SECS") {
+            config.ready_timeout_secs = timeout
+                .parse()
+                .map_err(|_| LCoreError::Config(format!("LCORE_READY_TIMEOUT_SECS is not a number: {}", timeout)))?;
+        }
+        if let Some(context) = var("LCORE_ENCRYPTION_CONTEXT") {
+            config.encryption_context = context;
+        }
+        if let Some(admins) = var("LCORE_ADMIN_ADDRESSES") {
+            config.auth.admin_addresses = admins
+                .split(',')
+                .map(str::trim)
+                .filter(|address| !address.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(required) = flag("LCORE_REQUIRE_DEVICE_SIGNATURE")? {
+            config.auth.require_device_signature = required;
+        }
+        if let Some(enabled) = flag("LCORE_FEATURE_ANALYTICS")? {
+            config.features.analytics = enabled;
+        }
+        if let Some(max_fields) = var("LCORE_ANALYTICS_MAX_FIELDS") {
+            config.features.analytics_max_fields = max_fields
+                .parse()
+                .map_err(|_| LCoreError::Config(format!("LCORE_ANALYTICS_MAX_FIELDS is not a number: {}", max_fields)))?;
+        }
+        if let Some(enabled) = flag("LCORE_FEATURE_RULES")? {
+            config.features.rules = enabled;
+        }
+        for (name, field) in [
+            ("LCORE_ETHER_PORTAL", &mut config.portals.ether_portal),
+            ("LCORE_ERC20_PORTAL", &mut config.portals.erc20_portal),
+            ("LCORE_DAPP_ADDRESS_RELAY", &mut config.portals.dapp_address_relay),
+            ("LCORE_REWARD_TOKEN", &mut config.rewards.token),
+            ("LCORE_REWARD_AMOUNT", &mut config.rewards.amount),
+            ("LCORE_REWARD_POOL", &mut config.rewards.pool),
+            ("LCORE_STAKE_AMOUNT", &mut config.staking.amount),
+            ("LCORE_TREASURY_ADDRESS", &mut config.staking.treasury),
+        ] {
+            if let Some(value) = var(name) {
+                *field = Some(value);
+            }
+        }
+        if let Some(every) = var("LCORE_REWARD_EVERY") {
+            config.rewards.every = every
+                .parse()
+                .map_err(|_| LCoreError::Config(format!("LCORE_REWARD_EVERY is not a number: {}", every)))?;
+        }
+        if let Some(seconds) = var("LCORE_STAKE_UNBONDING_SECONDS") {
+            config.staking.unbonding_seconds = seconds
+                .parse()
+                .map_err(|_| LCoreError::Config(format!("LCORE_STAKE_UNBONDING_SECONDS is not a number: {}", seconds)))?;
+        }
+        if let Some(asset) = var("LCORE_STAKE_ASSET") {
+            config.staking.asset = asset;
+        }
+        if let Some(level) = var("LCORE_LOG_LEVEL") {
+            config.logging.level = level;
+        }
+        if let Some(format) = var("LCORE_LOG_FORMAT") {
+            config.logging.format = match format.to_ascii_lowercase().as_str() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => return Err(LCoreError::Config(format!("LCORE_LOG_FORMAT must be text or json: {}", format))),
+            };
+        }
+        if let Some(redact) = flag("LCORE_LOG_REDACT")? {
+            config.logging.redact = redact;
+        }
+        if let Some(enabled) = flag("LCORE_DEV_API")? {
+            config.dev_api.enabled = enabled;
+        }
+        if let Some(address) = var("LCORE_DEV_API_BIND_ADDRESS") {
+            config.dev_api.bind_address = address;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check every setting, reporting all problems at once.
+    pub fn validate(&self) -> Result<(), LCoreError> {
+        let mut errors = Vec::new();
+        if !self.rollup_http_server_url.starts_with("http://") && !self.rollup_http_server_url.starts_with("https://") {
+            errors.push(format!("rollup_http_server_url must be an http(s) URL: {}", self.rollup_http_server_url));
+        }
+        if self.database_path.is_empty() {
+            errors.push("database_path must not be empty".to_string());
+        }
+        if let Err(e) = self.health_bind_address() {
+            errors.push(e.to_string());
+        }
+        if self.ready_timeout_secs == 0 {
+            errors.push("ready_timeout_secs must be positive".to_string());
+        }
+        if self.encryption_context.is_empty() {
+            errors.push("encryption_context must not be empty".to_string());
+        }
+        if let Err(e) = self.permissions() {
+            errors.push(format!("auth.admin_addresses: {}", e));
+        }
+        if let Err(e) = self.portals() {
+            errors.push(format!("portals: {}", e));
+        }
+        if let Err(e) = self.reward_policy() {
+            errors.push(format!("rewards: {}", e));
+        }
+        if let Err(e) = self.stake_policy() {
+            errors.push(format!("staking: {}", e));
+        }
+        if let Err(e) = logging::filter(&self.logging.level) {
+            errors.push(format!("logging: {}", e));
+        }
+        if let Err(e) = self.dev_api_bind_address() {
+            errors.push(e.to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(LCoreError::Config(errors.join("; ")))
+        }
+    }
+
+    pub fn health_bind_address(&self) -> Result<SocketAddr, LCoreError> {
+        self.health_bind_address
+            .parse()
+            .map_err(|_| LCoreError::Config(format!("health_bind_address is not a socket address: {}", self.health_bind_address)))
+    }
+
+    pub fn dev_api_bind_address(&self) -> Result<SocketAddr, LCoreError> {
+        self.dev_api
+            .bind_address
+            .parse()
+            .map_err(|_| LCoreError::Config(format!("dev_api.bind_address is not a socket address: {}", self.dev_api.bind_address)))
+    }
+
+    pub fn permissions(&self) -> Result<Permissions, LCoreError> {
+        Permissions::new(&self.auth.admin_addresses)
+    }
+
+    pub fn portals(&self) -> Result<Portals, LCoreError> {
+        let portals = Portals::new(self.portals.ether_portal.as_deref(), self.portals.erc20_portal.as_deref())?;
+        match &self.portals.dapp_address_relay {
+            Some(relay) => portals.with_dapp_address_relay(relay),
+            None => Ok(portals),
+        }
+    }
+
+    /// `None` when no reward token is configured
+    pub fn reward_policy(&self) -> Result<Option<RewardPolicy>, LCoreError> {
+        let token = match &self.rewards.token {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        let amount = match &self.rewards.amount {
+            Some(amount) => parse_amount("amount", amount)?,
+            None => return Err(LCoreError::Config("amount is required with token".to_string())),
+        };
+        let pool = self
+            .rewards
+            .pool
+            .as_deref()
+            .ok_or_else(|| LCoreError::Config("pool is required with token".to_string()))?;
+        RewardPolicy::new(token, amount, self.rewards.every, pool).map(Some)
+    }
+
+    /// `None` when no (or a zero) stake amount is configured
+    pub fn stake_policy(&self) -> Result<Option<StakePolicy>, LCoreError> {
+        let amount = match &self.staking.amount {
+            Some(amount) => parse_amount("amount", amount)?,
+            None => 0,
+        };
+        if amount == 0 {
+            return Ok(None);
+        }
+        let treasury = self
+            .staking
+            .treasury
+            .as_deref()
+            .ok_or_else(|| LCoreError::Config("treasury is required with amount".to_string()))?;
+        StakePolicy::new(Asset::parse(&self.staking.asset)?, amount, treasury, self.staking.unbonding_seconds).map(Some)
+    }
+}
+
+fn parse_amount(name: &str, amount: &str) -> Result<u128, LCoreError> {
+    amount
+        .parse()
+        .map_err(|_| LCoreError::Config(format!("{} is not a valid amount: {}", name, amount)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::from_sources(None, env(&[])).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.health_bind_address().unwrap(), "0.0.0.0:8000".parse().unwrap());
+        assert!(config.reward_policy().unwrap().is_none());
+        assert!(config.stake_policy().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_shipped_file_matches_defaults() {
+        let config = Config::from_sources(Some(include_str!("../lcore-node.toml")), env(&[])).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_file_then_env_overrides() {
+        let file = r#"
+            database_path = "/tmp/lcore.db"
+            encryption_context = "staging"
+
+            [auth]
+            admin_addresses = ["0x70997970C51812dc3A010C7d01b50e0d17dc79C8"]
+
+            [features]
+            rules = false
+
+            [staking]
+            amount = "100"
+            treasury = "0x90f79bf6eb2c4f870365e785982e1f101e93b906"
+        "#;
+        let config = Config::from_sources(Some(file), env(&[
+            ("LCORE_DATABASE_PATH", "/data/override.db"),
+            ("LCORE_REQUIRE_DEVICE_SIGNATURE", "true"),
+            ("LCORE_LOG_FORMAT", "JSON"),
+            // Empty values, as baked into the Dockerfile, leave the file setting alone
+            ("LCORE_ADMIN_ADDRESSES", ""),
+        ])).unwrap();
+
+        assert_eq!(config.database_path, "/data/override.db");
+        assert_eq!(config.encryption_context, "staging");
+        assert!(config.auth.require_device_signature);
+        assert!(!config.features.rules);
+        assert!(config.features.analytics);
+        assert!(config.permissions().unwrap().is_admin("0x70997970c51812dc3a010c7d01b50e0d17dc79c8"));
+        assert_eq!(config.stake_policy().unwrap().unwrap().amount, 100);
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert!(config.logging.redact);
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let error = Config::from_sources(None, env(&[
+            ("ROLLUP_HTTP_SERVER_URL", "127.0.0.1:5004"),
+            ("LCORE_HEALTH_BIND_ADDRESS", "localhost"),
+            ("LCORE_STAKE_AMOUNT", "100"),
+            ("LCORE_REWARD_TOKEN", "0x5fbdb2315678afecb367f032d93f642f64180aa3"),
+            ("LCORE_REWARD_AMOUNT", "1000"),
+            ("LCORE_LOG_LEVEL", "dapp=chatty"),
+        ]))
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("rollup_http_server_url"));
+        assert!(error.contains("health_bind_address"));
+        assert!(error.contains("treasury is required"));
+        assert!(error.contains("pool is required"));
+        assert!(error.contains("logging"));
+
+        assert!(Config::from_sources(Some("unknown_key = 1"), env(&[])).is_err());
+        assert!(Config::from_sources(None, env(&[("LCORE_FEATURE_RULES", "maybe")])).is_err());
+    }
+}
@@ -1,8 +1,21 @@
-use rusqlite::{Connection, Result, params};
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use crate::analytics::RunningStats;
+use crate::access::{AccessGrant, AccessPrice, MAX_STORED};
 use crate::error::LCoreError;
+use crate::portals::{Asset, Deposit};
+use crate::rollup::AdvanceMetadata;
+use crate::rules::{AlertState, Comparator};
+use crate::vouchers::Voucher;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::info;
 
 /// Database path within the Cartesi machine
-const DB_PATH: &str = "/data/iot.db";
+pub const DEFAULT_DB_PATH: &str = "/data/iot.db";
+
+/// Version stamped into `PRAGMA user_version` once `db/schema.sql` has been applied
+pub const SCHEMA_VERSION: i64 = 7;
 
 /// Database connection wrapper
 pub struct Database {
@@ -12,35 +25,617 @@ pub struct Database {
 impl Database {
     /// Initialize the database with IoT schema
     pub fn new() -> Result<Self, LCoreError> {
-        let conn = Connection::open(DB_PATH)?;
+        Self::open(DEFAULT_DB_PATH)
+    }
+
+    /// Open (or create) a database at `path` and apply the IoT schema if the
+    /// file predates `SCHEMA_VERSION`. `":memory:"` gives a throwaway database for tests.
+    pub fn open(path: &str) -> Result<Self, LCoreError> {
+        let conn = Connection::open(path)?;
         
         // Create the database schema
-        let schema = include_str!("../db/schema.sql");
-        conn.execute_batch(schema)?;
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version < SCHEMA_VERSION {
+            let schema = include_str!("../db/schema.sql");
+            conn.execute_batch(schema)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
         
-        println!("lcore-node: Database initialized at {}", DB_PATH);
+        info!("Database initialized at {}", path);
         
         Ok(Database { conn })
     }
     
-    /// Insert a new device into the devices table
-    pub fn insert_device(&self, device_id: &str, did_document: &str, public_key: &str) -> Result<(), LCoreError> {
-        // Insert or ignore device row
+    /// Open a savepoint; changes made until `Savepoint::commit` are rolled back if it is dropped.
+    /// Savepoints nest, so helpers that are atomic on their own can run inside a larger unit.
+    pub fn savepoint(&self) -> Result<Savepoint<'_>, LCoreError> {
+        self.conn.execute_batch("SAVEPOINT lcore")?;
+        Ok(Savepoint { conn: &self.conn, finished: false })
+    }
+
+    /// Schema version recorded in the database file
+    pub fn schema_version(&self) -> Result<i64, LCoreError> {
+        let version = self.conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        Ok(version)
+    }
+
+    /// Names of the schema's tables, sorted
+    fn table_names(&self) -> Result<Vec<String>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )?;
+        let tables = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(tables)
+    }
+
+    /// Number of rows in every table, by table name
+    pub fn row_counts(&self) -> Result<Vec<(String, u64)>, LCoreError> {
+        let tables = self.table_names()?;
+        let mut counts = Vec::with_capacity(tables.len());
+        for table in tables {
+            let count: u64 = self.conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| row.get(0))?;
+            counts.push((table, count));
+        }
+        Ok(counts)
+    }
+
+    /// SHA-256 over every row of every table, in table then rowid order.
+    ///
+    /// Two nodes that processed the same inputs hold the same rows, so equal
+    /// hashes mean equal state regardless of page layout or file size.
+    pub fn state_hash(&self) -> Result<[u8; 32], LCoreError> {
+        let mut hasher = Sha256::new();
+        for table in self.table_names()? {
+            hasher.update((table.len() as u64).to_be_bytes());
+            hasher.update(table.as_bytes());
+
+            let mut stmt = self.conn.prepare(&format!("SELECT * FROM \"{}\" ORDER BY rowid", table))?;
+            let columns = stmt.column_count();
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                for column in 0..columns {
+                    // Each value is tagged with its storage class so 1, 1.0 and '1' differ
+                    match row.get_ref(column)? {
+                        ValueRef::Null => hasher.update([0]),
+                        ValueRef::Integer(value) => {
+                            hasher.update([1]);
+                            hasher.update(value.to_be_bytes());
+                        }
+                        ValueRef::Real(value) => {
+                            hasher.update([2]);
+                            hasher.update(value.to_bits().to_be_bytes());
+                        }
+                        ValueRef::Text(bytes) => {
+                            hasher.update([3]);
+                            hasher.update((bytes.len() as u64).to_be_bytes());
+                            hasher.update(bytes);
+                        }
+                        ValueRef::Blob(bytes) => {
+                            hasher.update([4]);
+                            hasher.update((bytes.len() as u64).to_be_bytes());
+                            hasher.update(bytes);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// Size of the database in bytes
+    pub fn size_bytes(&self) -> Result<u64, LCoreError> {
+        let size = self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(size)
+    }
+
+    /// Whether the connection can take a write lock. The transaction is rolled
+    /// back straight away, so nothing is written.
+    pub fn is_writable(&self) -> bool {
+        self.conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;").is_ok()
+    }
+
+    /// Insert a new device into the devices table, stamped with the input that registered it.
+    /// The input's `msg_sender` becomes the device owner. An empty `authorized_senders` list leaves the device open to submissions from any sender.
+    /// An id that is already registered is refused.
+    pub fn insert_device(
+        &self,
+        device_id: &str,
+        did_document: &str,
+        public_key: &str,
+        device_type: Option<&str>,
+        authorized_senders: &[String],
+        metadata: &AdvanceMetadata,
+    ) -> Result<(), LCoreError> {
+        let created_at = metadata.timestamp_rfc3339()?;
+        let senders_json = if authorized_senders.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(authorized_senders)?)
+        };
+
+        let tx = self.savepoint()?;
+
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO devices (id, did_document, public_key, created_at, input_index, msg_sender, owner, authorized_senders, device_type)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?7, ?8)",
+            params![
+                device_id,
+                did_document,
+                public_key,
+                created_at,
+                metadata.input_index,
+                metadata.msg_sender,
+                senders_json,
+                device_type
+            ],
+        )?;
+
+        if inserted == 0 {
+            return Err(LCoreError::Policy(format!("Device {} is already registered", device_id)));
+        }
+
+        // The registration key opens the device's key history
         self.conn.execute(
-            "INSERT OR IGNORE INTO devices (id, did_document, public_key) VALUES (?1, ?2, ?3)",
-            params![device_id, did_document, public_key],
+            "INSERT INTO device_keys (device_id, public_key, valid_from_input, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![device_id, public_key, metadata.input_index, created_at],
         )?;
 
-        // Ensure there is a counter row initialised to 0
+        // Ensure there is a counter row initialised to 0; a re-registered id keeps its counter
         self.conn.execute(
             "INSERT OR IGNORE INTO device_counters (device_id, counter) VALUES (?1, 0)",
             params![device_id],
         )?;
 
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Fetch a device row, if registered
+    pub fn get_device(&self, device_id: &str) -> Result<Option<DeviceRow>, LCoreError> {
+        let device = self.conn.query_row(
+            "SELECT id, did_document, public_key, status, lifecycle_nonce, created_at, input_index, msg_sender, owner, device_type
+             FROM devices WHERE id = ?1",
+            params![device_id],
+            device_from_row,
+        ).optional()?;
+        Ok(device)
+    }
+
+    /// All registered devices, in registration order
+    pub fn list_devices(&self) -> Result<Vec<DeviceRow>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, did_document, public_key, status, lifecycle_nonce, created_at, input_index, msg_sender, owner, device_type
+             FROM devices ORDER BY input_index, id",
+        )?;
+        let devices = stmt.query_map([], device_from_row)?.collect::<Result<Vec<_>>>()?;
+        Ok(devices)
+    }
+
+    /// Replace a device's DID document and consume its lifecycle nonce
+    pub fn update_did_document(&self, device_id: &str, did_document: &str) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "UPDATE devices SET did_document = ?2, lifecycle_nonce = lifecycle_nonce + 1 WHERE id = ?1",
+            params![device_id, did_document],
+        )?;
+        Ok(())
+    }
+
+    /// Retire the device's current key and make `public_key` current from this input onwards.
+    /// Returns the id of the new key history row.
+    pub fn rotate_device_key(
+        &self,
+        device_id: &str,
+        public_key: &str,
+        metadata: &AdvanceMetadata,
+    ) -> Result<i64, LCoreError> {
+        let created_at = metadata.timestamp_rfc3339()?;
+        let tx = self.savepoint()?;
+
+        self.conn.execute(
+            "UPDATE device_keys SET valid_until_input = ?2 WHERE device_id = ?1 AND valid_until_input IS NULL",
+            params![device_id, metadata.input_index],
+        )?;
+        self.conn.execute(
+            "INSERT INTO device_keys (device_id, public_key, valid_from_input, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![device_id, public_key, metadata.input_index, created_at],
+        )?;
+        let key_id = self.conn.last_insert_rowid();
+        self.conn.execute(
+            "UPDATE devices SET public_key = ?2, lifecycle_nonce = lifecycle_nonce + 1 WHERE id = ?1",
+            params![device_id, public_key],
+        )?;
+
+        tx.commit()?;
+        Ok(key_id)
+    }
+
+    /// Change a device's lifecycle status and consume its lifecycle nonce
+    pub fn set_device_status(&self, device_id: &str, status: DeviceStatus) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "UPDATE devices SET status = ?2, lifecycle_nonce = lifecycle_nonce + 1 WHERE id = ?1",
+            params![device_id, status],
+        )?;
         Ok(())
     }
+
+    /// Grant `grantee` access to a device's data (idempotent). Purchased grants are kept separately.
+    pub fn grant_access(
+        &self,
+        device_id: &str,
+        grantee: &str,
+        metadata: &AdvanceMetadata,
+    ) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO access_grants (device_id, grantee, granted_by, input_index, purchased) VALUES (?1, ?2, ?3, ?4, 0)",
+            params![device_id, grantee, metadata.msg_sender, metadata.input_index],
+        )?;
+        Ok(())
+    }
+
+    /// Revoke access given with `grant_access`; returns whether such a grant existed.
+    /// Purchased grants are left alone.
+    pub fn revoke_access(&self, device_id: &str, grantee: &str) -> Result<bool, LCoreError> {
+        let removed = self.conn.execute(
+            "DELETE FROM access_grants WHERE device_id = ?1 AND grantee = ?2 AND purchased = 0",
+            params![device_id, grantee],
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// Whether `grantee` currently holds an access grant for the device
+    pub fn has_access(&self, device_id: &str, grantee: &str) -> Result<bool, LCoreError> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM access_grants WHERE device_id = ?1 AND grantee = ?2",
+            params![device_id, grantee],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    /// Every grant `grantee` holds for a device: one given by the owner and one purchased, at most
+    pub fn get_access_grants(&self, device_id: &str, grantee: &str) -> Result<Vec<AccessGrant>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT device_id, grantee, min_counter, max_counter, starts_at, expires_at, purchased FROM access_grants
+             WHERE device_id = ?1 AND grantee = ?2 ORDER BY purchased",
+        )?;
+        let grants = stmt
+            .query_map(params![device_id, grantee], access_grant_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(grants)
+    }
+
+    /// The grant `grantee` bought for a device, if any
+    pub fn get_purchased_grant(&self, device_id: &str, grantee: &str) -> Result<Option<AccessGrant>, LCoreError> {
+        let grant = self.conn.query_row(
+            "SELECT device_id, grantee, min_counter, max_counter, starts_at, expires_at, purchased FROM access_grants
+             WHERE device_id = ?1 AND grantee = ?2 AND purchased = 1",
+            params![device_id, grantee],
+            access_grant_from_row,
+        ).optional()?;
+        Ok(grant)
+    }
+
+    /// Store a purchased grant, replacing the grantee's previous purchase
+    pub fn upsert_access_grant(&self, grant: &AccessGrant, metadata: &AdvanceMetadata) -> Result<(), LCoreError> {
+        let min_counter = stored_integer(grant.min_counter, "min_counter")?;
+        let max_counter = stored_integer(grant.max_counter, "max_counter")?;
+        let starts_at = stored_integer(grant.starts_at, "starts_at")?;
+        let expires_at = stored_integer(grant.expires_at, "expires_at")?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO access_grants
+             (device_id, grantee, granted_by, input_index, min_counter, max_counter, starts_at, expires_at, purchased)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 1)",
+            params![
+                grant.device_id,
+                grant.grantee,
+                metadata.msg_sender,
+                metadata.input_index,
+                min_counter,
+                max_counter,
+                starts_at,
+                expires_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Set or replace the price of a device's data
+    pub fn set_access_price(&self, price: &AccessPrice, metadata: &AdvanceMetadata) -> Result<(), LCoreError> {
+        let period_seconds = stored_integer(price.period_seconds, "period_seconds")?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO access_prices (device_id, asset, price, period_seconds, set_by, input_index)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                price.device_id,
+                price.asset,
+                price.price.to_string(),
+                period_seconds,
+                metadata.msg_sender,
+                metadata.input_index,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Price of a device's data; `None` when it is not for sale
+    pub fn get_access_price(&self, device_id: &str) -> Result<Option<AccessPrice>, LCoreError> {
+        let row = self.conn.query_row(
+            "SELECT device_id, asset, price, period_seconds FROM access_prices WHERE device_id = ?1",
+            params![device_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Asset>(1)?, row.get::<_, String>(2)?, row.get(3)?)),
+        ).optional()?;
+
+        match row {
+            Some((device_id, asset, price, period_seconds)) => Ok(Some(AccessPrice {
+                device_id,
+                asset,
+                price: parse_amount(&price)?,
+                period_seconds,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Set or replace the key that signs `address`'s inspect queries
+    pub fn set_reader_key(&self, address: &str, public_key: &str, metadata: &AdvanceMetadata) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO reader_keys (address, public_key, input_index) VALUES (?1, ?2, ?3)",
+            params![address, public_key, metadata.input_index],
+        )?;
+        Ok(())
+    }
+
+    /// Public JWK registered by `address` for its inspect queries
+    pub fn get_reader_key(&self, address: &str) -> Result<Option<String>, LCoreError> {
+        let key = self.conn.query_row(
+            "SELECT public_key FROM reader_keys WHERE address = ?1",
+            params![address],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(key)
+    }
+
+    /// Remove a device together with its readings, keys, grants, analytics,
+    /// rules, alerts and reward progress. Rules on its device type stay.
+    /// The message counter is kept so a re-registered DID never reuses a nonce.
+    pub fn delete_device(&self, device_id: &str) -> Result<(), LCoreError> {
+        let tx = self.savepoint()?;
+        for table in [
+            "sensor_data",
+            "device_keys",
+            "access_grants",
+            "access_prices",
+            "analytics",
+            "analytics_windows",
+            "alerts",
+            "rule_states",
+            "rules",
+            "reward_progress",
+        ] {
+            self.conn.execute(
+                &format!("DELETE FROM {} WHERE device_id = ?1", table),
+                params![device_id],
+            )?;
+        }
+        self.conn.execute("DELETE FROM devices WHERE id = ?1", params![device_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Register or replace the reading schema of a device type; returns the new version
+    pub fn upsert_device_schema(
+        &self,
+        device_type: &str,
+        schema: &str,
+        metadata: &AdvanceMetadata,
+    ) -> Result<u64, LCoreError> {
+        self.conn.execute(
+            "INSERT INTO device_schemas (device_type, schema, registered_by, input_index) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(device_type) DO UPDATE SET
+               schema = excluded.schema,
+               version = version + 1,
+               registered_by = excluded.registered_by,
+               input_index = excluded.input_index",
+            params![device_type, schema, metadata.msg_sender, metadata.input_index],
+        )?;
+
+        let version: u64 = self.conn.query_row(
+            "SELECT version FROM device_schemas WHERE device_type = ?1",
+            params![device_type],
+            |row| row.get(0),
+        )?;
+        Ok(version)
+    }
+
+    /// Fetch the schema definition of a device type, if registered
+    pub fn get_device_schema(&self, device_type: &str) -> Result<Option<String>, LCoreError> {
+        let schema = self.conn.query_row(
+            "SELECT schema FROM device_schemas WHERE device_type = ?1",
+            params![device_type],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(schema)
+    }
+
+    /// Store a rule; returns its id
+    pub fn insert_rule(&self, rule: &RuleRow, metadata: &AdvanceMetadata) -> Result<i64, LCoreError> {
+        self.conn.execute(
+            "INSERT INTO rules (device_id, device_type, field, comparator, threshold, hysteresis, slash_amount, created_by, input_index)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                rule.device_id,
+                rule.device_type,
+                rule.field,
+                rule.comparator,
+                rule.threshold,
+                rule.hysteresis,
+                rule.slash_amount.map(|amount| amount.to_string()),
+                metadata.msg_sender,
+                metadata.input_index
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Fetch a rule by id
+    pub fn get_rule(&self, rule_id: i64) -> Result<Option<RuleRow>, LCoreError> {
+        let rule = self.conn.query_row(
+            "SELECT id, device_id, device_type, field, comparator, threshold, hysteresis, slash_amount FROM rules WHERE id = ?1",
+            params![rule_id],
+            rule_from_row,
+        ).optional()?;
+        Ok(rule)
+    }
+
+    /// Delete a rule and its per-device state; alerts it raised are kept
+    pub fn remove_rule(&self, rule_id: i64) -> Result<bool, LCoreError> {
+        let sp = self.savepoint()?;
+        self.conn.execute("DELETE FROM rule_states WHERE rule_id = ?1", params![rule_id])?;
+        let removed = self.conn.execute("DELETE FROM rules WHERE id = ?1", params![rule_id])?;
+        sp.commit()?;
+        Ok(removed > 0)
+    }
+
+    /// Rules targeting the device directly or through its device type, oldest first
+    pub fn get_rules_for_device(&self, device_id: &str, device_type: Option<&str>) -> Result<Vec<RuleRow>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, device_id, device_type, field, comparator, threshold, hysteresis, slash_amount
+             FROM rules
+             WHERE device_id = ?1 OR (device_type IS NOT NULL AND device_type = ?2)
+             ORDER BY id ASC"
+        )?;
+
+        let rows = stmt.query_map(params![device_id, device_type], rule_from_row)?;
+        let mut rules = Vec::new();
+        for row in rows {
+            rules.push(row?);
+        }
+        Ok(rules)
+    }
+
+    /// Whether a rule is currently in alert for a device
+    pub fn get_rule_state(&self, rule_id: i64, device_id: &str) -> Result<bool, LCoreError> {
+        let active: Option<bool> = self.conn.query_row(
+            "SELECT active FROM rule_states WHERE rule_id = ?1 AND device_id = ?2",
+            params![rule_id, device_id],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(active.unwrap_or(false))
+    }
+
+    /// Record whether a rule is in alert for a device
+    pub fn set_rule_state(&self, rule_id: i64, device_id: &str, active: bool) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "INSERT INTO rule_states (rule_id, device_id, active) VALUES (?1, ?2, ?3)
+             ON CONFLICT(rule_id, device_id) DO UPDATE SET active = excluded.active",
+            params![rule_id, device_id, active],
+        )?;
+        Ok(())
+    }
+
+    /// Record an alert transition raised by a rule
+    pub fn insert_alert(
+        &self,
+        rule: &RuleRow,
+        device_id: &str,
+        value: f64,
+        state: AlertState,
+        metadata: &AdvanceMetadata,
+    ) -> Result<AlertRow, LCoreError> {
+        let timestamp = metadata.timestamp_rfc3339()?;
+        self.conn.execute(
+            "INSERT INTO alerts (rule_id, device_id, field, value, threshold, state, input_index, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                rule.id,
+                device_id,
+                rule.field,
+                value,
+                rule.threshold,
+                state.as_str(),
+                metadata.input_index,
+                timestamp
+            ],
+        )?;
+
+        Ok(AlertRow {
+            id: self.conn.last_insert_rowid(),
+            rule_id: rule.id,
+            device_id: device_id.to_string(),
+            field: rule.field.clone(),
+            value,
+            threshold: rule.threshold,
+            state: state.as_str().to_string(),
+            input_index: metadata.input_index,
+            timestamp,
+        })
+    }
+
+    /// Alerts raised for a device, newest first
+    pub fn get_alerts(&self, device_id: &str) -> Result<Vec<AlertRow>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, rule_id, device_id, field, value, threshold, state, input_index, timestamp
+             FROM alerts
+             WHERE device_id = ?1
+             ORDER BY id DESC"
+        )?;
+
+        let rows = stmt.query_map(params![device_id], |row| {
+            Ok(AlertRow {
+                id: row.get(0)?,
+                rule_id: row.get(1)?,
+                device_id: row.get(2)?,
+                field: row.get(3)?,
+                value: row.get(4)?,
+                threshold: row.get(5)?,
+                state: row.get(6)?,
+                input_index: row.get(7)?,
+                timestamp: row.get(8)?,
+            })
+        })?;
+
+        let mut alerts = Vec::new();
+        for row in rows {
+            alerts.push(row?);
+        }
+        Ok(alerts)
+    }
+
+    /// Full key history of a device, oldest first
+    pub fn get_device_keys(&self, device_id: &str) -> Result<Vec<DeviceKeyRow>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, device_id, public_key, valid_from_input, valid_until_input, created_at
+             FROM device_keys
+             WHERE device_id = ?1
+             ORDER BY id ASC"
+        )?;
+
+        let rows = stmt.query_map(params![device_id], |row| {
+            Ok(DeviceKeyRow {
+                id: row.get(0)?,
+                device_id: row.get(1)?,
+                public_key: row.get(2)?,
+                valid_from_input: row.get(3)?,
+                valid_until_input: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        })?;
+
+        let mut keys = Vec::new();
+        for row in rows {
+            keys.push(row?);
+        }
+
+        Ok(keys)
+    }
     
-    /// Insert encrypted sensor data
+    /// Insert encrypted sensor data, timestamped with the block time of the input
     pub fn insert_sensor_data(
         &self,
         device_id: &str,
@@ -48,11 +643,23 @@ impl Database {
         stage1_key_hash: &str,
         stage2_key_hash: &str,
         counter: u64,
-        timestamp: &str,
+        metadata: &AdvanceMetadata,
     ) -> Result<(), LCoreError> {
+        let timestamp = metadata.timestamp_rfc3339()?;
         self.conn.execute(
-            "INSERT INTO sensor_data (device_id, encrypted_payload, stage1_key_hash, stage2_key_hash, counter, timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![device_id, encrypted_payload, stage1_key_hash, stage2_key_hash, counter, timestamp],
+            "INSERT INTO sensor_data (device_id, encrypted_payload, stage1_key_hash, stage2_key_hash, counter, timestamp, input_index, msg_sender, key_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8,
+                     (SELECT id FROM device_keys WHERE device_id = ?1 AND valid_until_input IS NULL))",
+            params![
+                device_id,
+                encrypted_payload,
+                stage1_key_hash,
+                stage2_key_hash,
+                counter,
+                timestamp,
+                metadata.input_index,
+                metadata.msg_sender
+            ],
         )?;
         Ok(())
     }
@@ -74,44 +681,122 @@ impl Database {
         Ok(counter)
     }
     
+    /// Number of readings ever stored for a device, i.e. its current message counter
+    pub fn get_message_counter(&self, device_id: &str) -> Result<u64, LCoreError> {
+        let counter: Option<u64> = self.conn.query_row(
+            "SELECT counter FROM device_counters WHERE device_id = ?1",
+            params![device_id],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(counter.unwrap_or(0))
+    }
+
     /// Get the latest sensor data for a device
     pub fn get_latest_sensor_data(&self, device_id: &str) -> Result<Option<SensorDataRow>, LCoreError> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, device_id, encrypted_payload, stage1_key_hash, stage2_key_hash, counter, timestamp 
+            "SELECT id, device_id, encrypted_payload, stage1_key_hash, stage2_key_hash, counter, timestamp, input_index, msg_sender, key_id
              FROM sensor_data 
              WHERE device_id = ?1 
-             ORDER BY timestamp DESC 
+             ORDER BY input_index DESC, id DESC 
              LIMIT 1"
         )?;
         
         let mut rows = stmt.query(params![device_id])?;
         
         if let Some(row) = rows.next()? {
-            Ok(Some(SensorDataRow {
-                id: row.get(0)?,
-                device_id: row.get(1)?,
-                encrypted_payload: row.get(2)?,
-                stage1_key_hash: row.get(3)?,
-                stage2_key_hash: row.get(4)?,
-                counter: row.get(5)?,
-                timestamp: row.get(6)?,
-            }))
+            Ok(Some(sensor_data_from_row(row)?))
         } else {
             Ok(None)
         }
     }
+
+    /// Up to `limit` most recent readings of a device, newest first
+    pub fn get_sensor_history(&self, device_id: &str, limit: u32) -> Result<Vec<SensorDataRow>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, device_id, encrypted_payload, stage1_key_hash, stage2_key_hash, counter, timestamp, input_index, msg_sender, key_id
+             FROM sensor_data
+             WHERE device_id = ?1
+             ORDER BY input_index DESC, id DESC
+             LIMIT ?2"
+        )?;
+        let rows = stmt
+            .query_map(params![device_id, limit], sensor_data_from_row)?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    /// Highest input index that registered a device or stored a reading
+    pub fn max_input_index(&self) -> Result<Option<u64>, LCoreError> {
+        let index = self.conn.query_row(
+            "SELECT MAX(input_index) FROM (SELECT input_index FROM devices UNION ALL SELECT input_index FROM sensor_data)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(index)
+    }
     
-    /// Insert analytics data
+    /// Insert analytics data, replacing the value already published for the same window
     pub fn insert_analytics(
         &self,
         device_id: &str,
         metric_type: &str,
         value: f64,
         time_window: &str,
+        metadata: &AdvanceMetadata,
+    ) -> Result<(), LCoreError> {
+        let calculated_at = metadata.timestamp_rfc3339()?;
+        self.conn.execute(
+            "INSERT INTO analytics (device_id, metric_type, value, time_window, calculated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(device_id, metric_type, time_window) DO UPDATE SET
+               value = excluded.value,
+               calculated_at = excluded.calculated_at",
+            params![device_id, metric_type, value, time_window, calculated_at],
+        )?;
+        Ok(())
+    }
+
+    /// Fetch the running aggregate of a metric in one window, if any reading landed in it
+    pub fn get_analytics_window(
+        &self,
+        device_id: &str,
+        metric: &str,
+        time_window: &str,
+    ) -> Result<Option<RunningStats>, LCoreError> {
+        let stats = self.conn.query_row(
+            "SELECT count, mean, m2, min, max FROM analytics_windows
+             WHERE device_id = ?1 AND metric = ?2 AND time_window = ?3",
+            params![device_id, metric, time_window],
+            |row| {
+                Ok(RunningStats {
+                    count: row.get(0)?,
+                    mean: row.get(1)?,
+                    m2: row.get(2)?,
+                    min: row.get(3)?,
+                    max: row.get(4)?,
+                })
+            },
+        ).optional()?;
+        Ok(stats)
+    }
+
+    /// Store the running aggregate of a metric in one window
+    pub fn upsert_analytics_window(
+        &self,
+        device_id: &str,
+        metric: &str,
+        time_window: &str,
+        stats: &RunningStats,
     ) -> Result<(), LCoreError> {
         self.conn.execute(
-            "INSERT INTO analytics (device_id, metric_type, value, time_window) VALUES (?1, ?2, ?3, ?4)",
-            params![device_id, metric_type, value, time_window],
+            "INSERT INTO analytics_windows (device_id, metric, time_window, count, mean, m2, min, max)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT(device_id, metric, time_window) DO UPDATE SET
+               count = excluded.count,
+               mean = excluded.mean,
+               m2 = excluded.m2,
+               min = excluded.min,
+               max = excluded.max",
+            params![device_id, metric, time_window, stats.count, stats.mean, stats.m2, stats.min, stats.max],
         )?;
         Ok(())
     }
@@ -122,7 +807,7 @@ impl Database {
             "SELECT id, device_id, metric_type, value, time_window, calculated_at 
              FROM analytics 
              WHERE device_id = ?1 AND metric_type = ?2 
-             ORDER BY calculated_at DESC"
+             ORDER BY calculated_at DESC, id DESC"
         )?;
         
         let rows = stmt.query_map(params![device_id, metric_type], |row| {
@@ -157,6 +842,374 @@ impl Database {
             Ok(None)
         }
     }
+
+    /// Fetch the addresses allowed to submit on behalf of a device.
+    /// Returns `None` when the device is unknown or accepts any sender.
+    pub fn get_authorized_senders(&self, device_id: &str) -> Result<Option<Vec<String>>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT authorized_senders FROM devices WHERE id = ?1 LIMIT 1",
+        )?;
+        let mut rows = stmt.query(params![device_id])?;
+        if let Some(row) = rows.next()? {
+            let senders: Option<String> = row.get(0)?;
+            match senders {
+                Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+                None => Ok(None),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Count one more rewarded-eligible reading for a device; returns the new total
+    pub fn increment_reward_progress(&self, device_id: &str) -> Result<u64, LCoreError> {
+        self.conn.execute(
+            "INSERT INTO reward_progress (device_id, readings) VALUES (?1, 1)
+             ON CONFLICT(device_id) DO UPDATE SET readings = readings + 1",
+            params![device_id],
+        )?;
+
+        let readings: u64 = self.conn.query_row(
+            "SELECT readings FROM reward_progress WHERE device_id = ?1",
+            params![device_id],
+            |row| row.get(0),
+        )?;
+        Ok(readings)
+    }
+
+    /// Record an emitted voucher; returns its id
+    pub fn insert_voucher(
+        &self,
+        voucher: &Voucher,
+        reason: &str,
+        metadata: &AdvanceMetadata,
+    ) -> Result<i64, LCoreError> {
+        self.conn.execute(
+            "INSERT INTO vouchers (destination, payload, reason, input_index) VALUES (?1, ?2, ?3, ?4)",
+            params![voucher.destination, voucher.payload, reason, metadata.input_index],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Vouchers emitted by the dApp, oldest first
+    pub fn get_vouchers(&self) -> Result<Vec<VoucherRow>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, destination, payload, reason, input_index FROM vouchers ORDER BY id"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(VoucherRow {
+                id: row.get(0)?,
+                destination: row.get(1)?,
+                payload: row.get(2)?,
+                reason: row.get(3)?,
+                input_index: row.get(4)?,
+            })
+        })?;
+
+        let mut vouchers = Vec::new();
+        for row in rows {
+            vouchers.push(row?);
+        }
+        Ok(vouchers)
+    }
+
+    /// Record a portal deposit and credit it to the depositor's balance; returns the new
+    /// balance. A deposit the ledger cannot hold is kept in `unclaimed_deposits` instead,
+    /// and `None` is returned.
+    pub fn record_deposit(&self, deposit: &Deposit, metadata: &AdvanceMetadata) -> Result<Option<u128>, LCoreError> {
+        let balance = match deposit.amount.to_u128() {
+            Some(amount) => self.get_balance(&deposit.depositor, &deposit.asset)?.checked_add(amount),
+            None => None,
+        };
+        let table = if balance.is_some() { "deposits" } else { "unclaimed_deposits" };
+
+        let tx = self.savepoint()?;
+        self.conn.execute(
+            &format!("INSERT INTO {} (asset, depositor, amount, input_index) VALUES (?1, ?2, ?3, ?4)", table),
+            params![deposit.asset, deposit.depositor, deposit.amount.to_string(), metadata.input_index],
+        )?;
+        if let Some(balance) = balance {
+            self.set_balance(&deposit.depositor, &deposit.asset, balance)?;
+        }
+        tx.commit()?;
+        Ok(balance)
+    }
+
+    /// Deposits of `depositor` held unclaimed, as (asset, uint256 decimal amount), oldest first
+    pub fn get_unclaimed_deposits(&self, depositor: &str) -> Result<Vec<(Asset, String)>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT asset, amount FROM unclaimed_deposits WHERE depositor = ?1 ORDER BY id"
+        )?;
+        let deposits = stmt
+            .query_map(params![depositor], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(deposits)
+    }
+
+    /// Balance of `address` in `asset`; zero when it never held any
+    pub fn get_balance(&self, address: &str, asset: &Asset) -> Result<u128, LCoreError> {
+        let amount: Option<String> = self.conn.query_row(
+            "SELECT amount FROM balances WHERE address = ?1 AND asset = ?2",
+            params![address, asset],
+            |row| row.get(0),
+        ).optional()?;
+        amount.map_or(Ok(0), |amount| parse_amount(&amount))
+    }
+
+    /// Every non-zero balance of `address`, by asset
+    pub fn get_balances(&self, address: &str) -> Result<Vec<(Asset, u128)>, LCoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT asset, amount FROM balances WHERE address = ?1 ORDER BY asset"
+        )?;
+        let rows = stmt.query_map(params![address], |row| {
+            Ok((row.get::<_, Asset>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut balances = Vec::new();
+        for row in rows {
+            let (asset, amount) = row?;
+            let amount = parse_amount(&amount)?;
+            if amount > 0 {
+                balances.push((asset, amount));
+            }
+        }
+        Ok(balances)
+    }
+
+    /// Add `amount` to a balance; returns the new balance
+    pub fn credit_balance(&self, address: &str, asset: &Asset, amount: u128) -> Result<u128, LCoreError> {
+        let balance = self
+            .get_balance(address, asset)?
+            .checked_add(amount)
+            .ok_or_else(|| LCoreError::InvalidInput(format!("Balance overflow for {}", address)))?;
+        self.set_balance(address, asset, balance)?;
+        Ok(balance)
+    }
+
+    /// Subtract `amount` from a balance; returns false, changing nothing, if it is insufficient
+    pub fn debit_balance(&self, address: &str, asset: &Asset, amount: u128) -> Result<bool, LCoreError> {
+        match self.get_balance(address, asset)?.checked_sub(amount) {
+            Some(balance) => {
+                self.set_balance(address, asset, balance)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Amounts are u128, beyond SQLite's 64-bit integers, so they are stored as decimal text
+    fn set_balance(&self, address: &str, asset: &Asset, amount: u128) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "INSERT INTO balances (address, asset, amount) VALUES (?1, ?2, ?3)
+             ON CONFLICT(address, asset) DO UPDATE SET amount = excluded.amount",
+            params![address, asset, amount.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Record a device's locked stake
+    pub fn insert_stake(
+        &self,
+        device_id: &str,
+        owner: &str,
+        asset: &Asset,
+        amount: u128,
+        metadata: &AdvanceMetadata,
+    ) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "INSERT INTO stakes (device_id, owner, asset, amount, input_index) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![device_id, owner, asset, amount.to_string(), metadata.input_index],
+        )?;
+        Ok(())
+    }
+
+    /// A device's stake, if it locked one
+    pub fn get_stake(&self, device_id: &str) -> Result<Option<StakeRow>, LCoreError> {
+        let row = self.conn.query_row(
+            "SELECT device_id, owner, asset, amount, unbonding_until FROM stakes WHERE device_id = ?1",
+            params![device_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Asset>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, Option<u64>>(4)?,
+                ))
+            },
+        ).optional()?;
+
+        match row {
+            Some((device_id, owner, asset, amount, unbonding_until)) => Ok(Some(StakeRow {
+                device_id,
+                owner,
+                asset,
+                amount: parse_amount(&amount)?,
+                unbonding_until,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Overwrite what is left of a device's stake
+    pub fn set_stake_amount(&self, device_id: &str, amount: u128) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "UPDATE stakes SET amount = ?2 WHERE device_id = ?1",
+            params![device_id, amount.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Start unbonding a device's stake; it can be released from `until` on
+    pub fn set_stake_unbonding(&self, device_id: &str, until: u64) -> Result<(), LCoreError> {
+        let until = stored_integer(Some(until), "unbonding_until")?;
+        self.conn.execute(
+            "UPDATE stakes SET unbonding_until = ?2 WHERE device_id = ?1",
+            params![device_id, until],
+        )?;
+        Ok(())
+    }
+
+    /// Forget a device's stake once it has been released
+    pub fn delete_stake(&self, device_id: &str) -> Result<(), LCoreError> {
+        self.conn.execute("DELETE FROM stakes WHERE device_id = ?1", params![device_id])?;
+        Ok(())
+    }
+
+    /// Record a slash for audit
+    pub fn insert_slash(
+        &self,
+        device_id: &str,
+        asset: &Asset,
+        amount: u128,
+        reason: &str,
+        metadata: &AdvanceMetadata,
+    ) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "INSERT INTO slashes (device_id, asset, amount, reason, slashed_by, input_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![device_id, asset, amount.to_string(), reason, metadata.msg_sender, metadata.input_index],
+        )?;
+        Ok(())
+    }
+
+    /// Store the dApp's own address as relayed by the DAppAddressRelay
+    pub fn set_dapp_address(&self, address: &str, metadata: &AdvanceMetadata) -> Result<(), LCoreError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO dapp_address (id, address, input_index) VALUES (1, ?1, ?2)",
+            params![address, metadata.input_index],
+        )?;
+        Ok(())
+    }
+
+    /// The dApp's own address; `None` until it has been relayed
+    pub fn get_dapp_address(&self) -> Result<Option<String>, LCoreError> {
+        let address = self.conn.query_row(
+            "SELECT address FROM dapp_address WHERE id = 1",
+            [],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(address)
+    }
+}
+
+/// SQLite integers are signed, so larger values are refused before a write
+/// fails on them; the input is rejected rather than halting the node.
+fn stored_integer(value: Option<u64>, field: &str) -> Result<Option<u64>, LCoreError> {
+    match value {
+        Some(value) if value > MAX_STORED => {
+            Err(LCoreError::InvalidInput(format!("{} {} is out of range", field, value)))
+        }
+        value => Ok(value),
+    }
+}
+
+fn parse_amount(amount: &str) -> Result<u128, LCoreError> {
+    amount
+        .parse()
+        .map_err(|_| LCoreError::Internal(format!("Corrupt ledger amount: {}", amount)))
+}
+
+fn access_grant_from_row(row: &rusqlite::Row<'_>) -> Result<AccessGrant> {
+    Ok(AccessGrant {
+        device_id: row.get(0)?,
+        grantee: row.get(1)?,
+        min_counter: row.get(2)?,
+        max_counter: row.get(3)?,
+        starts_at: row.get(4)?,
+        expires_at: row.get(5)?,
+        purchased: row.get(6)?,
+    })
+}
+
+fn device_from_row(row: &rusqlite::Row<'_>) -> Result<DeviceRow> {
+    Ok(DeviceRow {
+        id: row.get(0)?,
+        did_document: row.get(1)?,
+        public_key: row.get(2)?,
+        status: row.get(3)?,
+        lifecycle_nonce: row.get(4)?,
+        created_at: row.get(5)?,
+        input_index: row.get(6)?,
+        msg_sender: row.get(7)?,
+        owner: row.get(8)?,
+        device_type: row.get(9)?,
+    })
+}
+
+fn sensor_data_from_row(row: &rusqlite::Row<'_>) -> Result<SensorDataRow> {
+    Ok(SensorDataRow {
+        id: row.get(0)?,
+        device_id: row.get(1)?,
+        encrypted_payload: row.get(2)?,
+        stage1_key_hash: row.get(3)?,
+        stage2_key_hash: row.get(4)?,
+        counter: row.get(5)?,
+        timestamp: row.get(6)?,
+        input_index: row.get(7)?,
+        msg_sender: row.get(8)?,
+        key_id: row.get(9)?,
+    })
+}
+
+fn rule_from_row(row: &rusqlite::Row<'_>) -> Result<RuleRow> {
+    Ok(RuleRow {
+        id: row.get(0)?,
+        device_id: row.get(1)?,
+        device_type: row.get(2)?,
+        field: row.get(3)?,
+        comparator: row.get(4)?,
+        threshold: row.get(5)?,
+        hysteresis: row.get(6)?,
+        slash_amount: row
+            .get::<_, Option<String>>(7)?
+            .map(|amount| amount.parse::<u128>())
+            .transpose()
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(7, rusqlite::types::Type::Text, Box::new(e)))?,
+    })
+}
+
+/// Guard for an open savepoint, see `Database::savepoint`
+pub struct Savepoint<'a> {
+    conn: &'a Connection,
+    finished: bool,
+}
+
+impl Savepoint<'_> {
+    /// Keep the changes made since the savepoint was opened
+    pub fn commit(mut self) -> Result<(), LCoreError> {
+        self.finished = true;
+        self.conn.execute_batch("RELEASE lcore")?;
+        Ok(())
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.conn.execute_batch("ROLLBACK TO lcore; RELEASE lcore");
+        }
+    }
 }
 
 /// Sensor data row structure
@@ -169,10 +1222,120 @@ pub struct SensorDataRow {
     pub stage2_key_hash: String,
     pub counter: u64,
     pub timestamp: String,
+    pub input_index: u64,
+    pub msg_sender: String,
+    pub key_id: Option<i64>,
 }
 
-/// Analytics row structure
+/// Device lifecycle status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceStatus {
+    Active,
+    Deactivated,
+}
+
+impl DeviceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceStatus::Active => "active",
+            DeviceStatus::Deactivated => "deactivated",
+        }
+    }
+}
+
+impl ToSql for DeviceStatus {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for DeviceStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "active" => Ok(DeviceStatus::Active),
+            "deactivated" => Ok(DeviceStatus::Deactivated),
+            other => Err(FromSqlError::Other(format!("Unknown device status: {}", other).into())),
+        }
+    }
+}
+
+/// Device row structure
+#[derive(Debug, Clone)]
+pub struct DeviceRow {
+    pub id: String,
+    pub did_document: String,
+    pub public_key: String,
+    pub status: DeviceStatus,
+    pub lifecycle_nonce: u64,
+    pub created_at: String,
+    pub input_index: u64,
+    pub msg_sender: String,
+    pub owner: String,
+    pub device_type: Option<String>,
+}
+
+/// Device key history row structure
+#[derive(Debug, Clone)]
+pub struct DeviceKeyRow {
+    pub id: i64,
+    pub device_id: String,
+    pub public_key: String,
+    pub valid_from_input: u64,
+    pub valid_until_input: Option<u64>,
+    pub created_at: String,
+}
+
+/// Threshold rule row structure; exactly one of `device_id` / `device_type` is set
 #[derive(Debug, Clone)]
+pub struct RuleRow {
+    pub id: i64,
+    pub device_id: Option<String>,
+    pub device_type: Option<String>,
+    pub field: String,
+    pub comparator: Comparator,
+    pub threshold: f64,
+    pub hysteresis: f64,
+    /// Stake slashed from the device each time the rule triggers
+    pub slash_amount: Option<u128>,
+}
+
+/// Alert row structure
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertRow {
+    pub id: i64,
+    pub rule_id: i64,
+    pub device_id: String,
+    pub field: String,
+    pub value: f64,
+    pub threshold: f64,
+    pub state: String,
+    pub input_index: u64,
+    pub timestamp: String,
+}
+
+/// Stake row structure
+#[derive(Debug, Clone)]
+pub struct StakeRow {
+    pub device_id: String,
+    pub owner: String,
+    pub asset: Asset,
+    pub amount: u128,
+    /// Set once the device is deleted: when the stake can be released
+    pub unbonding_until: Option<u64>,
+}
+
+/// Voucher row structure
+#[derive(Debug, Clone)]
+pub struct VoucherRow {
+    pub id: i64,
+    pub destination: String,
+    pub payload: Vec<u8>,
+    pub reason: String,
+    pub input_index: u64,
+}
+
+/// Analytics row structure
+#[derive(Debug, Clone, Serialize)]
 pub struct AnalyticsRow {
     pub id: i32,
     pub device_id: String,
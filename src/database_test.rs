@@ -1,73 +1,83 @@
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::database::{Database, SensorDataRow};
+    use crate::access::AccessGrant;
+    use crate::database::{Database, DeviceStatus, RuleRow, SCHEMA_VERSION};
     use crate::encryption::{Stage1Encryption, Stage2Encryption};
+    use crate::error::LCoreError;
+    use crate::portals::{Asset, Deposit, Uint256};
+    use crate::rollup::AdvanceMetadata;
+    use crate::rules::{AlertState, Comparator};
     use sha2::{Digest, Sha256};
-    use hex;
+
+    const OWNER: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
 
     #[test]
     fn test_database_initialization() {
         // Test database creation following cartesi-risczero pattern
-        let db = Database::new().expect("Failed to create database");
+        let db = Database::open(":memory:").expect("Failed to create database");
         
         // Verify database connection is working
-        assert!(db.conn.is_ok());
+        let count: i64 = db.conn
+            .query_row("SELECT COUNT(*) FROM devices", [], |row| row.get(0))
+            .expect("Failed to query devices");
+        assert_eq!(count, 0);
     }
 
     #[test]
     fn test_device_insertion() {
-        let db = Database::new().expect("Failed to create database");
+        let db = Database::open(":memory:").expect("Failed to create database");
         
         // Test device insertion following IoT schema
         let device_id = "did:example:123456789";
         let did_document = "{\"id\":\"did:example:123456789\",\"publicKey\":[]}";
         let public_key = "{\"kty\":\"OKP\",\"crv\":\"Ed25519\"}";
         
-        let result = db.insert_device(device_id, did_document, public_key);
+        let result = db.insert_device(device_id, did_document, public_key, None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200));
         assert!(result.is_ok());
+
+        // A second registration of the id is refused
+        let error = db.insert_device(device_id, "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap_err();
+        assert!(matches!(error, LCoreError::Policy(_)));
     }
 
     #[test]
     fn test_sensor_data_insertion() {
-        let db = Database::new().expect("Failed to create database");
+        let db = Database::open(":memory:").expect("Failed to create database");
         
         // First insert device
         let device_id = "did:example:123456789";
-        db.insert_device(device_id, "{}", "{}").expect("Failed to insert device");
+        db.insert_device(device_id, "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).expect("Failed to insert device");
         
         // Test sensor data insertion following dual encryption pattern
         let test_data = b"temperature:23.5,humidity:45.2";
         let stage1_key = Stage1Encryption::derive_key_from_did(device_id).expect("Failed to derive key");
         let stage2_key = Stage2Encryption::derive_key_from_context("iot-sensor-data-v1").expect("Failed to derive key");
         
-        let stage1_hash = hex::encode(Sha256::digest(&stage1_key));
-        let stage2_hash = hex::encode(Sha256::digest(&stage2_key));
-        let timestamp = "2024-01-01T00:00:00Z";
-        
+        let stage1_hash = hex::encode(Sha256::digest(stage1_key));
+        let stage2_hash = hex::encode(Sha256::digest(stage2_key));
         let result = db.insert_sensor_data(
             device_id,
             test_data,
             &stage1_hash,
             &stage2_hash,
             1,
-            timestamp,
+            &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200),
         );
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_sensor_data_retrieval() {
-        let db = Database::new().expect("Failed to create database");
+        let db = Database::open(":memory:").expect("Failed to create database");
         
         // Setup test data
         let device_id = "did:example:123456789";
-        db.insert_device(device_id, "{}", "{}").expect("Failed to insert device");
+        db.insert_device(device_id, "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).expect("Failed to insert device");
         
         let test_data = b"temperature:23.5,humidity:45.2";
-        let timestamp = "2024-01-01T00:00:00Z";
+        let timestamp = "2024-01-01T00:00:00+00:00";
         
-        db.insert_sensor_data(device_id, test_data, "hash1", "hash2", 1, timestamp)
+        db.insert_sensor_data(device_id, test_data, "hash1", "hash2", 1, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200))
             .expect("Failed to insert sensor data");
         
         // Test retrieval
@@ -80,9 +90,30 @@ mod tests {
         assert_eq!(sensor_data.timestamp, timestamp);
     }
 
+    #[test]
+    fn test_device_listing_and_sensor_history() {
+        let db = Database::open(":memory:").unwrap();
+        assert_eq!(db.max_input_index().unwrap(), None);
+
+        let mut metadata = AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200);
+        db.insert_device("did:example:a", "{}", "{}", None, &[], &metadata).unwrap();
+        db.insert_device("did:example:b", "{}", "{}", None, &[], &metadata).unwrap();
+        for counter in 1..=3 {
+            metadata.input_index = counter;
+            db.insert_sensor_data("did:example:a", b"reading", "hash1", "hash2", counter, &metadata).unwrap();
+        }
+
+        let ids: Vec<String> = db.list_devices().unwrap().into_iter().map(|device| device.id).collect();
+        assert_eq!(ids, ["did:example:a", "did:example:b"]);
+        let history = db.get_sensor_history("did:example:a", 2).unwrap();
+        assert_eq!(history.iter().map(|row| row.counter).collect::<Vec<_>>(), [3, 2]);
+        assert!(db.get_sensor_history("did:example:b", 10).unwrap().is_empty());
+        assert_eq!(db.max_input_index().unwrap(), Some(3));
+    }
+
     #[test]
     fn test_analytics_insertion() {
-        let db = Database::new().expect("Failed to create database");
+        let db = Database::open(":memory:").expect("Failed to create database");
         
         // Test analytics insertion following IoT schema
         let device_id = "did:example:123456789";
@@ -90,18 +121,18 @@ mod tests {
         let value = 23.5;
         let time_window = "1h";
         
-        let result = db.insert_analytics(device_id, metric_type, value, time_window);
+        let result = db.insert_analytics(device_id, metric_type, value, time_window, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200));
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_end_to_end_iot_flow() {
         // Test complete IoT data flow following cartesi-risczero pattern
-        let db = Database::new().expect("Failed to create database");
+        let db = Database::open(":memory:").expect("Failed to create database");
         
         // 1. Device registration
         let device_did = "did:example:123456789";
-        db.insert_device(device_did, "{}", "{}").expect("Device registration failed");
+        db.insert_device(device_did, "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).expect("Device registration failed");
         
         // 2. Dual encryption
         let original_data = b"sensor_reading:temperature=23.5,humidity=45.2,pressure=1013.25";
@@ -115,11 +146,9 @@ mod tests {
         let ciphertext2 = stage2.encrypt(&ciphertext1).expect("Stage 2 encryption failed");
         
         // 3. Database storage
-        let key1_hash = hex::encode(Sha256::digest(&key1));
-        let key2_hash = hex::encode(Sha256::digest(&key2));
-        let timestamp = "2024-01-01T00:00:00Z";
-        
-        db.insert_sensor_data(device_did, &ciphertext2, &key1_hash, &key2_hash, 1, timestamp)
+        let key1_hash = hex::encode(Sha256::digest(key1));
+        let key2_hash = hex::encode(Sha256::digest(key2));
+        db.insert_sensor_data(device_did, &ciphertext2, &key1_hash, &key2_hash, 1, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200))
             .expect("Data storage failed");
         
         // 4. Data retrieval and decryption
@@ -133,10 +162,230 @@ mod tests {
         assert_eq!(decrypted_original, original_data);
         
         // 5. Analytics
-        db.insert_analytics(device_did, "temperature", 23.5, "1h").expect("Analytics insertion failed");
+        db.insert_analytics(device_did, "temperature", 23.5, "1h", &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).expect("Analytics insertion failed");
         
         let analytics = db.get_analytics(device_did, "temperature").expect("Analytics retrieval failed");
         assert!(!analytics.is_empty());
         assert_eq!(analytics[0].value, 23.5);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_authorized_senders() {
+        let db = Database::open(":memory:").expect("Failed to create database");
+
+        // Devices registered without a gateway list accept any sender
+        db.insert_device("did:example:open", "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200))
+            .expect("Failed to insert device");
+        assert!(db.get_authorized_senders("did:example:open").unwrap().is_none());
+
+        let gateways = vec!["0x70997970c51812dc3a010c7d01b50e0d17dc79c8".to_string()];
+        db.insert_device("did:example:gated", "{}", "{}", None, &gateways, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200))
+            .expect("Failed to insert device");
+        assert_eq!(db.get_authorized_senders("did:example:gated").unwrap(), Some(gateways));
+    }
+
+    #[test]
+    fn test_device_lifecycle_and_key_history() {
+        let db = Database::open(":memory:").expect("Failed to create database");
+        let device_id = "did:example:123456789";
+        db.insert_device(device_id, "{}", "old-key", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200))
+            .expect("Failed to insert device");
+
+        db.insert_sensor_data(device_id, b"before", "hash1", "hash2", 1, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200))
+            .expect("Failed to insert sensor data");
+        let old_key_id = db.get_latest_sensor_data(device_id).unwrap().unwrap().key_id;
+
+        let mut rotation = AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200);
+        rotation.input_index = 5;
+        let new_key_id = db.rotate_device_key(device_id, "new-key", &rotation)
+            .expect("Key rotation failed");
+
+        db.insert_sensor_data(device_id, b"after", "hash1", "hash2", 2, &rotation)
+            .expect("Failed to insert sensor data");
+        let latest = db.get_latest_sensor_data(device_id).unwrap().unwrap();
+        assert_eq!(latest.key_id, Some(new_key_id));
+        assert_ne!(old_key_id, Some(new_key_id));
+
+        let keys = db.get_device_keys(device_id).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].public_key, "old-key");
+        assert_eq!(keys[0].valid_until_input, Some(5));
+        assert_eq!(keys[1].valid_until_input, None);
+
+        db.set_device_status(device_id, DeviceStatus::Deactivated).unwrap();
+        let device = db.get_device(device_id).unwrap().unwrap();
+        assert_eq!(device.public_key, "new-key");
+        assert_eq!(device.status, DeviceStatus::Deactivated);
+        assert_eq!(device.lifecycle_nonce, 2);
+    }
+
+    #[test]
+    fn test_access_grants_and_deletion() {
+        let db = Database::open(":memory:").expect("Failed to create database");
+        let device_id = "did:example:123456789";
+        let grantee = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+        db.insert_device(device_id, "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200))
+            .expect("Failed to insert device");
+
+        let device = db.get_device(device_id).unwrap().unwrap();
+        assert_eq!(device.owner, OWNER);
+
+        db.grant_access(device_id, grantee, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        assert!(db.has_access(device_id, grantee).unwrap());
+        assert!(db.revoke_access(device_id, grantee).unwrap());
+        assert!(!db.revoke_access(device_id, grantee).unwrap());
+
+        // Owner grants and revocations leave a purchased grant alone
+        let purchased = AccessGrant {
+            device_id: device_id.to_string(),
+            grantee: grantee.to_string(),
+            min_counter: Some(0),
+            max_counter: Some(5),
+            starts_at: None,
+            expires_at: None,
+            purchased: true,
+        };
+        db.upsert_access_grant(&purchased, &AdvanceMetadata::for_test(grantee, 1, 1_704_067_200)).unwrap();
+        db.grant_access(device_id, grantee, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        assert_eq!(db.get_access_grants(device_id, grantee).unwrap().len(), 2);
+        assert!(db.revoke_access(device_id, grantee).unwrap());
+        assert!(!db.revoke_access(device_id, grantee).unwrap());
+        assert_eq!(db.get_purchased_grant(device_id, grantee).unwrap(), Some(purchased));
+
+        db.insert_sensor_data(device_id, b"reading", "hash1", "hash2", 1, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        let mut rule = RuleRow {
+            id: 0,
+            device_id: Some(device_id.to_string()),
+            device_type: None,
+            field: "temperature".to_string(),
+            comparator: Comparator::Gt,
+            threshold: 25.0,
+            hysteresis: 0.0,
+            slash_amount: None,
+        };
+        rule.id = db.insert_rule(&rule, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        db.set_rule_state(rule.id, device_id, true).unwrap();
+        db.insert_alert(&rule, device_id, 30.0, AlertState::Triggered, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        db.increment_reward_progress(device_id).unwrap();
+        assert_eq!(db.next_message_counter(device_id).unwrap(), 1);
+
+        db.delete_device(device_id).unwrap();
+        assert!(db.get_device(device_id).unwrap().is_none());
+        assert!(db.get_latest_sensor_data(device_id).unwrap().is_none());
+        assert!(db.get_device_keys(device_id).unwrap().is_empty());
+
+        // A re-registered DID starts without the previous owner's rules, alerts or rewards
+        db.insert_device(device_id, "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        assert!(db.get_rules_for_device(device_id, None).unwrap().is_empty());
+        assert!(!db.get_rule_state(rule.id, device_id).unwrap());
+        assert!(db.get_alerts(device_id).unwrap().is_empty());
+        assert_eq!(db.increment_reward_progress(device_id).unwrap(), 1);
+        assert_eq!(db.next_message_counter(device_id).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_savepoint_rolls_back_when_dropped() {
+        let db = Database::open(":memory:").expect("Failed to create database");
+        let device_id = "did:example:123456789";
+
+        let savepoint = db.savepoint().unwrap();
+        db.insert_device(device_id, "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        assert_eq!(db.next_message_counter(device_id).unwrap(), 1);
+        drop(savepoint);
+        assert!(db.get_device(device_id).unwrap().is_none());
+
+        let savepoint = db.savepoint().unwrap();
+        db.insert_device(device_id, "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        savepoint.commit().unwrap();
+        assert!(db.get_device(device_id).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_device_schemas() {
+        let db = Database::open(":memory:").expect("Failed to create database");
+
+        // Built-in types are seeded by the schema
+        assert!(db.get_device_schema("temperature").unwrap().is_some());
+        assert!(db.get_device_schema("unknown").unwrap().is_none());
+
+        let cold_chain = r#"{"fields":{"temperature":{"type":"number","max":8}}}"#;
+        assert_eq!(db.upsert_device_schema("cold_chain", cold_chain, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap(), 1);
+        assert_eq!(db.upsert_device_schema("cold_chain", cold_chain, &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap(), 2);
+
+        db.insert_device("did:example:fridge", "{}", "{}", Some("cold_chain"), &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        let device = db.get_device("did:example:fridge").unwrap().unwrap();
+        assert_eq!(device.device_type.as_deref(), Some("cold_chain"));
+    }
+
+    #[test]
+    fn test_deposits_and_balances() {
+        let db = Database::open(":memory:").unwrap();
+        let metadata = AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200);
+        let depositor = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+        let amount = u128::from(u64::MAX) * 4;
+        let mut deposit = Deposit {
+            asset: Asset::Ether,
+            depositor: depositor.to_string(),
+            amount: Uint256::from(amount),
+            exec_layer_data: Vec::new(),
+        };
+
+        assert_eq!(db.record_deposit(&deposit, &metadata).unwrap(), Some(amount));
+        assert_eq!(db.record_deposit(&deposit, &metadata).unwrap(), Some(amount * 2));
+
+        assert!(!db.debit_balance(depositor, &Asset::Ether, amount * 3).unwrap());
+        assert!(db.debit_balance(depositor, &Asset::Ether, amount).unwrap());
+        assert_eq!(db.get_balance(depositor, &Asset::Ether).unwrap(), amount);
+
+        let token = Asset::Erc20("0x5fbdb2315678afecb367f032d93f642f64180aa3".to_string());
+        assert_eq!(db.get_balance(depositor, &token).unwrap(), 0);
+        assert_eq!(db.get_balances(depositor).unwrap(), vec![(Asset::Ether, amount)]);
+
+        // Deposits the ledger cannot hold are kept unclaimed and leave the balance alone
+        deposit.amount = Uint256::from(u128::MAX);
+        assert_eq!(db.record_deposit(&deposit, &metadata).unwrap(), None);
+        deposit.amount = Uint256([0xff; 32]);
+        assert_eq!(db.record_deposit(&deposit, &metadata).unwrap(), None);
+        assert_eq!(db.get_balance(depositor, &Asset::Ether).unwrap(), amount);
+        let unclaimed = db.get_unclaimed_deposits(depositor).unwrap();
+        assert_eq!(unclaimed.len(), 2);
+        assert_eq!(unclaimed[0], (Asset::Ether, u128::MAX.to_string()));
+        assert_eq!(unclaimed[1].1, deposit.amount.to_string());
+    }
+
+    #[test]
+    fn test_dapp_address() {
+        let db = Database::open(":memory:").unwrap();
+        assert_eq!(db.get_dapp_address().unwrap(), None);
+
+        db.set_dapp_address("0x243e9f2ee6af8f1c0783ff10452cc1486820552c", &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        db.set_dapp_address("0x70997970c51812dc3a010c7d01b50e0d17dc79c8", &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        assert_eq!(db.get_dapp_address().unwrap().as_deref(), Some("0x70997970c51812dc3a010c7d01b50e0d17dc79c8"));
+    }
+
+    #[test]
+    fn test_status_queries() {
+        let db = Database::open(":memory:").unwrap();
+        assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+        assert!(db.is_writable());
+        assert!(db.size_bytes().unwrap() > 0);
+
+        db.insert_device("did:example:status", "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        let counts = db.row_counts().unwrap();
+        assert!(counts.contains(&("devices".to_string(), 1)));
+        assert!(counts.contains(&("sensor_data".to_string(), 0)));
+        assert!(counts.iter().all(|(table, _)| !table.starts_with("sqlite_")));
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let a = Database::open(":memory:").unwrap();
+        let b = Database::open(":memory:").unwrap();
+        assert_eq!(a.state_hash().unwrap(), b.state_hash().unwrap());
+
+        a.insert_device("did:example:hash", "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        assert_ne!(a.state_hash().unwrap(), b.state_hash().unwrap());
+        b.insert_device("did:example:hash", "{}", "{}", None, &[], &AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200)).unwrap();
+        assert_eq!(a.state_hash().unwrap(), b.state_hash().unwrap());
+    }
+}
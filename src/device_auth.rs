@@ -49,3 +49,102 @@ pub fn verify_device_signature(jws: &str, payload: &[u8], public_jwk_json: &str)
     Ok(())
 }
 
+
+/// Checks that a JWK is an Ed25519 public key, so a device cannot rotate to an
+/// unusable key or publish its private half on chain.
+pub fn validate_public_jwk(public_jwk_json: &str) -> Result<(), LCoreError> {
+    let jwk = Jwk::from_bytes(public_jwk_json.as_bytes())
+        .map_err(|e| LCoreError::DeviceAuth(format!("Failed to parse JWK: {}", e)))?;
+    if jwk.key_type() != "OKP" || jwk.curve() != Some("Ed25519") {
+        return Err(LCoreError::DeviceAuth("JWK is not an Ed25519 key".to_string()));
+    }
+    if jwk.parameter("d").is_some() {
+        return Err(LCoreError::DeviceAuth("JWK carries a private key".to_string()));
+    }
+    Ok(())
+}
+
+/// Canonical message a device key must sign to authorize a lifecycle action.
+///
+/// The device's lifecycle nonce is included so a captured signature cannot be
+/// replayed once the action has been applied (e.g. re-deactivating a device
+/// after it was reactivated). `value` is the new DID document or public key,
+/// or empty for status changes.
+pub fn lifecycle_message(action: &str, device_id: &str, nonce: u64, value: &str) -> String {
+    format!("{}:{}:{}:{}", action, device_id, nonce, value)
+}
+
+/// Canonical message a device key must sign to submit a reading:
+/// `reading:<device_id>:<counter>:` followed by the raw reading bytes.
+///
+/// `counter` is the message counter the reading will be stored under, one
+/// past the device's current counter, so a captured signature is stale as
+/// soon as the reading it was made for has been accepted.
+pub fn reading_message(device_id: &str, counter: u64, data: &[u8]) -> Vec<u8> {
+    let mut message = format!("reading:{}:{}:", device_id, counter).into_bytes();
+    message.extend_from_slice(data);
+    message
+}
+
+/// Canonical message a device key must sign to submit a batch:
+/// `batch:<device_id>:<leaf_count>:<hex root>`, where `root` is the
+/// `batch::merkle_root` of the batch's reading leaves.
+///
+/// The leaf count pins the batch size the root was built from, so the
+/// signature cannot be reused for a different number of readings.
+pub fn batch_message(device_id: &str, leaf_count: usize, root: &[u8; 32]) -> String {
+    format!("batch:{}:{}:{}", device_id, leaf_count, hex::encode(root))
+}
+
+/// Canonical message a consumer's reader key must sign to read a device's data.
+///
+/// The challenge is the counter of the device's latest reading, so a captured
+/// query unlocks nothing newer than what its signer was already shown.
+pub fn read_message(device_id: &str, counter: u64) -> String {
+    format!("read:{}:{}", device_id, counter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Keypair, Signer};
+
+    /// Builds a compact JWS over `payload` and the matching public JWK.
+    fn sign(keypair: &Keypair, payload: &[u8]) -> (String, String) {
+        let header = general_purpose::URL_SAFE_NO_PAD.encode(br#"{"alg":"EdDSA"}"#);
+        let body = general_purpose::URL_SAFE_NO_PAD.encode(payload);
+        let signature = keypair.sign(format!("{}.{}", header, body).as_bytes());
+        let jws = format!("{}.{}.{}", header, body, general_purpose::URL_SAFE_NO_PAD.encode(signature.to_bytes()));
+        let jwk = format!(
+            r#"{{"kty":"OKP","crv":"Ed25519","x":"{}"}}"#,
+            general_purpose::URL_SAFE_NO_PAD.encode(keypair.public.to_bytes())
+        );
+        (jws, jwk)
+    }
+
+    #[test]
+    fn test_lifecycle_signature_is_bound_to_nonce() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+        let message = lifecycle_message("deactivate", "did:example:123", 0, "");
+        let (jws, jwk) = sign(&keypair, message.as_bytes());
+
+        assert!(validate_public_jwk(&jwk).is_ok());
+        assert!(verify_device_signature(&jws, message.as_bytes(), &jwk).is_ok());
+
+        // The same signature must not authorize the action once the nonce has moved on
+        let replayed = lifecycle_message("deactivate", "did:example:123", 1, "");
+        assert!(verify_device_signature(&jws, replayed.as_bytes(), &jwk).is_err());
+    }
+
+    #[test]
+    fn test_validate_public_jwk_rejects_other_keys() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+        let x = general_purpose::URL_SAFE_NO_PAD.encode(keypair.public.to_bytes());
+        let d = general_purpose::URL_SAFE_NO_PAD.encode(keypair.secret.to_bytes());
+
+        assert!(validate_public_jwk(&format!(r#"{{"kty":"OKP","crv":"Ed25519","x":"{}"}}"#, x)).is_ok());
+        assert!(validate_public_jwk(&format!(r#"{{"kty":"OKP","crv":"X25519","x":"{}"}}"#, x)).is_err());
+        assert!(validate_public_jwk(r#"{"kty":"oct","k":"c2VjcmV0"}"#).is_err());
+        assert!(validate_public_jwk(&format!(r#"{{"kty":"OKP","crv":"Ed25519","x":"{}","d":"{}"}}"#, x, d)).is_err());
+    }
+}
@@ -1,59 +1,150 @@
-use std::fmt;
+// lcore-node/src/error.rs
+//
+// The node's single error model. Every error is either the input's fault
+// (client: the input is rejected and an error report explains why) or the
+// node's (server: the rollup is told through /exception).
+
+use hyper::StatusCode;
+use json::{object, JsonValue};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum LCoreError {
-    #[error("Database error: {0}")]
-    Database(#[from] rusqlite::Error),
-    
-    #[error("Encryption error: {0}")]
-    Encryption(String),
-    
-    #[error("Device authentication error: {0}")]
-    DeviceAuth(String),
-    
-    #[error("Internal error: {0}")]
-    Internal(String),
-    
+    // --- Rollup protocol ---
+    #[error("Rollup server error: {0}")]
+    Rollup(String),
+
+    /// A request from the rollup server that does not follow the protocol
+    #[error("Malformed rollup request: {0}")]
+    Protocol(String),
+
+    // --- Payload decoding ---
     #[error("Invalid input: {0}")]
     InvalidInput(String),
-    
+
     #[error("Hex decode error: {0}")]
     HexDecode(#[from] hex::FromHexError),
-    
+
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
-    
+
     #[error("UTF-8 error: {0}")]
     Utf8(#[from] std::str::Utf8Error),
-}
 
-// Legacy AppError for backward compatibility
-#[derive(Debug)]
-pub enum AppError {
+    // --- Authentication ---
+    #[error("Device authentication error: {0}")]
+    DeviceAuth(String),
+
+    // --- Cryptography ---
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
+    // --- Storage ---
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    // --- Policy ---
+    /// The input is well-formed but not allowed: permissions, balances, device state
+    #[error("{0}")]
+    Policy(String),
+
+    // --- Node ---
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    #[error("Internal error: {0}")]
     Internal(String),
-    InvalidInput(String),
 }
 
-impl fmt::Display for AppError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+/// Whose fault an error is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The input is at fault: reject it, state is reverted
+    Client,
+    /// The node is at fault: raise an exception instead of guessing
+    Server,
+}
+
+impl LCoreError {
+    pub fn class(&self) -> ErrorClass {
         match self {
-            AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
-            AppError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            LCoreError::InvalidInput(_)
+            | LCoreError::HexDecode(_)
+            | LCoreError::Json(_)
+            | LCoreError::Utf8(_)
+            | LCoreError::DeviceAuth(_)
+            | LCoreError::Policy(_) => ErrorClass::Client,
+            LCoreError::Rollup(_)
+            | LCoreError::Protocol(_)
+            | LCoreError::Encryption(_)
+            | LCoreError::Database(_)
+            | LCoreError::Config(_)
+            | LCoreError::Internal(_) => ErrorClass::Server,
         }
     }
-}
 
-impl std::error::Error for AppError {}
+    /// Stable name of the variant, for reports and API bodies
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LCoreError::Rollup(_) => "rollup",
+            LCoreError::Protocol(_) => "protocol",
+            LCoreError::InvalidInput(_) | LCoreError::HexDecode(_) | LCoreError::Json(_) | LCoreError::Utf8(_) => {
+                "invalid_input"
+            }
+            LCoreError::DeviceAuth(_) => "device_auth",
+            LCoreError::Encryption(_) => "encryption",
+            LCoreError::Database(_) => "database",
+            LCoreError::Policy(_) => "policy",
+            LCoreError::Config(_) => "config",
+            LCoreError::Internal(_) => "internal",
+        }
+    }
 
-impl From<anyhow::Error> for AppError {
-    fn from(err: anyhow::Error) -> Self {
-        AppError::Internal(err.to_string())
+    /// Report explaining a rejected input
+    pub fn report(&self) -> JsonValue {
+        object! {
+            "type" => "error",
+            "kind" => self.kind(),
+            "message" => self.to_string(),
+        }
+    }
+
+    /// Status for HTTP front-ends such as the developer API
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            LCoreError::DeviceAuth(_) => StatusCode::UNAUTHORIZED,
+            LCoreError::Policy(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            LCoreError::Rollup(_) => StatusCode::BAD_GATEWAY,
+            _ if self.class() == ErrorClass::Client => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 }
 
-impl From<LCoreError> for AppError {
-    fn from(err: LCoreError) -> Self {
-        AppError::Internal(err.to_string())
+impl From<std::string::FromUtf8Error> for LCoreError {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        LCoreError::Utf8(err.utf8_error())
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        let policy = LCoreError::Policy("device is deactivated".to_string());
+        assert_eq!(policy.class(), ErrorClass::Client);
+        assert_eq!(policy.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(policy.report()["kind"], "policy");
+
+        let hex = LCoreError::from(hex::decode("zz").unwrap_err());
+        assert_eq!(hex.class(), ErrorClass::Client);
+        assert_eq!(hex.status_code(), StatusCode::BAD_REQUEST);
+
+        let storage = LCoreError::Database(rusqlite::Error::InvalidQuery);
+        assert_eq!(storage.class(), ErrorClass::Server);
+        assert_eq!(storage.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(LCoreError::Protocol("no payload".to_string()).class(), ErrorClass::Server);
+    }
+}
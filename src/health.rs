@@ -0,0 +1,228 @@
+// lcore-node/src/health.rs
+//
+// Liveness and readiness endpoints, served next to the rollup loop
+
+use crate::database::Database;
+use crate::error::LCoreError;
+use crate::metrics::METRICS;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use json::{object, JsonValue};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+/// How often the rollup loop re-reads the database snapshot; counting every
+/// table's rows after each input would cost more than the input itself.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Database status as last seen by the rollup loop. The health server only
+/// reads this snapshot, so it never opens or locks the database itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseStatus {
+    pub writable: bool,
+    pub schema_version: i64,
+    pub size_bytes: u64,
+    pub row_counts: Vec<(String, u64)>,
+}
+
+impl DatabaseStatus {
+    pub fn read(db: &Database) -> Result<Self, LCoreError> {
+        Ok(Self {
+            writable: db.is_writable(),
+            schema_version: db.schema_version()?,
+            size_bytes: db.size_bytes()?,
+            row_counts: db.row_counts()?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct LoopStatus {
+    last_finish: Option<Instant>,
+    last_input_index: Option<u64>,
+    database: Option<DatabaseStatus>,
+    /// When `database` was last read, whether or not the read succeeded
+    snapshot_at: Option<Instant>,
+}
+
+/// State the rollup loop shares with the health server
+#[derive(Debug)]
+pub struct HealthState {
+    ready_timeout: Duration,
+    status: Mutex<LoopStatus>,
+}
+
+impl HealthState {
+    pub fn new(ready_timeout: Duration) -> Self {
+        Self {
+            ready_timeout,
+            status: Mutex::new(LoopStatus::default()),
+        }
+    }
+
+    /// Called by the rollup loop after every successful `/finish` round trip.
+    pub fn record_finish(&self) {
+        self.status.lock().unwrap().last_finish = Some(Instant::now());
+    }
+
+    /// Called by the rollup loop between requests, on its own connection.
+    /// `input_index` is the advance input just processed, if any; the
+    /// database snapshot is re-read at most once per `SNAPSHOT_INTERVAL`.
+    pub fn refresh(&self, db: &Database, input_index: Option<u64>) {
+        {
+            let mut status = self.status.lock().unwrap();
+            if input_index.is_some() {
+                status.last_input_index = input_index;
+            }
+            if status.snapshot_at.is_some_and(|at| at.elapsed() < SNAPSHOT_INTERVAL) {
+                return;
+            }
+        }
+
+        let database = match DatabaseStatus::read(db) {
+            Ok(database) => Some(database),
+            Err(e) => {
+                warn!("Failed to read database status: {}", e);
+                None
+            }
+        };
+        let mut status = self.status.lock().unwrap();
+        status.database = database;
+        status.snapshot_at = Some(Instant::now());
+    }
+
+    /// Whether the rollup loop reached `/finish` within the ready timeout.
+    pub fn finish_is_recent(&self) -> bool {
+        self.status
+            .lock()
+            .unwrap()
+            .last_finish
+            .is_some_and(|at| at.elapsed() <= self.ready_timeout)
+    }
+
+    /// Index of the last advance input the rollup loop processed
+    pub fn last_input_index(&self) -> Option<u64> {
+        self.status.lock().unwrap().last_input_index
+    }
+
+    /// The last published database snapshot
+    pub fn database(&self) -> Option<DatabaseStatus> {
+        self.status.lock().unwrap().database.clone()
+    }
+}
+
+/// Serve `/livez`, `/readyz`, the legacy `/health` and `/metrics` on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, state: Arc<HealthState>) {
+    let make_svc = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                let state = state.clone();
+                async move { Ok::<_, hyper::Error>(respond(&req, &state)) }
+            }))
+        }
+    });
+
+    if let Err(e) = Server::bind(&addr).serve(make_svc).await {
+        error!("Health server error: {}", e);
+    }
+}
+
+/// Liveness only says the process answers; readiness also needs a recent
+/// `/finish` from the rollup loop and a writable database.
+fn respond(req: &Request<Body>, state: &HealthState) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/livez") => json_response(StatusCode::OK, object! {
+            status: "alive",
+            service: "lcore-node",
+        }),
+        (&Method::GET, "/readyz") | (&Method::GET, "/health") => {
+            let rollup_loop = state.finish_is_recent();
+            let database = state.database();
+            let writable = database.as_ref().is_some_and(|database| database.writable);
+            let ready = rollup_loop && writable;
+
+            let mut payload = object! {
+                status: if ready { "ready" } else { "not_ready" },
+                rollup_loop: if rollup_loop { "ok" } else { "stale" },
+                database: if writable { "ok" } else { "error" },
+                last_input_index: state.last_input_index(),
+                service: "lcore-node",
+            };
+            if let Some(database) = database {
+                payload["schema_version"] = database.schema_version.into();
+                let mut rows = JsonValue::new_object();
+                for (table, count) in database.row_counts {
+                    rows[table.as_str()] = count.into();
+                }
+                payload["rows"] = rows;
+            }
+            json_response(
+                if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE },
+                payload,
+            )
+        }
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(METRICS.render(state)))
+            .unwrap(),
+        _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap(),
+    }
+}
+
+fn json_response(status: StatusCode, payload: JsonValue) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(payload.dump()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::SCHEMA_VERSION;
+    use crate::rollup::AdvanceMetadata;
+
+    fn get(path: &str) -> Request<Body> {
+        Request::builder().uri(path).body(Body::empty()).unwrap()
+    }
+
+    async fn body_json(response: Response<Body>) -> JsonValue {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        json::parse(std::str::from_utf8(&body).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_readiness_follows_rollup_loop_and_database() {
+        let db = Database::open(":memory:").unwrap();
+        let state = HealthState::new(Duration::from_secs(30));
+        assert_eq!(respond(&get("/livez"), &state).status(), StatusCode::OK);
+        assert_eq!(respond(&get("/readyz"), &state).status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        state.record_finish();
+        assert_eq!(respond(&get("/readyz"), &state).status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        state.refresh(&db, Some(7));
+        // Inputs within the snapshot interval move the index but not the snapshot
+        let metadata = AdvanceMetadata::for_test("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266", 8, 1_704_067_200);
+        db.insert_device("did:example:health", "{}", "{}", None, &[], &metadata).unwrap();
+        state.refresh(&db, Some(8));
+        state.refresh(&db, None);
+        let response = respond(&get("/readyz"), &state);
+        assert_eq!(response.status(), StatusCode::OK);
+        let payload = body_json(response).await;
+        assert_eq!(payload["last_input_index"], 8);
+        assert_eq!(payload["schema_version"], SCHEMA_VERSION);
+        assert_eq!(payload["rows"]["devices"], 0);
+        assert_eq!(respond(&get("/metrics"), &state).status(), StatusCode::OK);
+        assert_eq!(respond(&get("/unknown"), &state).status(), StatusCode::NOT_FOUND);
+
+        let stale = HealthState::new(Duration::ZERO);
+        stale.record_finish();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(!stale.finish_is_recent());
+    }
+}
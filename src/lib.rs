@@ -3,9 +3,35 @@
 // within a Cartesi rollups environment
 
 pub mod error;
+pub mod analytics;
+pub mod abi;
+pub mod access;
+pub mod api;
+pub mod app;
+pub mod batch;
+pub mod codec;
+pub mod config;
 pub mod database;
 pub mod encryption;
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod payloads;
 pub mod device_auth;
+pub mod permissions;
+pub mod portals;
+#[cfg(feature = "harness")]
+pub mod replay;
+pub mod rollup;
+#[cfg(feature = "harness")]
+pub mod rollup_stub;
+pub mod rules;
+pub mod schema;
+pub mod staking;
+pub mod vouchers;
+
+#[cfg(test)]
+mod database_test;
 
 use serde::{Deserialize, Serialize};
 
@@ -34,11 +60,3 @@ pub struct EncryptedSensorPayload {
     pub stage2_key_hash: String,
     pub timestamp: String,
 }
-
-// Application state for Cartesi integration
-#[derive(Debug, Clone)]
-pub struct AppState {
-    // Database is now handled by the Database struct
-    // Encryption is handled by Stage1Encryption and Stage2Encryption
-    // Device authentication is handled by device_auth module
-} 
\ No newline at end of file
@@ -0,0 +1,76 @@
+// lcore-node/src/logging.rs
+//
+// Leveled logging in text or JSON, with payloads and key material redacted by default
+
+use crate::config::{LogFormat, LoggingConfig};
+use crate::error::LCoreError;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+static REDACT: AtomicBool = AtomicBool::new(true);
+
+/// Install the global subscriber. Call once, before the rollup loop starts.
+pub fn init(config: &LoggingConfig) -> Result<(), LCoreError> {
+    install(config, std::io::stdout)
+}
+
+/// Like [`init`], but log to stderr, for tools whose stdout is their result.
+pub fn init_stderr(config: &LoggingConfig) -> Result<(), LCoreError> {
+    install(config, std::io::stderr)
+}
+
+fn install<W>(config: &LoggingConfig, writer: W) -> Result<(), LCoreError>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    REDACT.store(config.redact, Ordering::Relaxed);
+    let builder = tracing_subscriber::fmt().with_env_filter(filter(&config.level)?).with_writer(writer);
+    let installed = match config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    };
+    installed.map_err(|e| LCoreError::Config(format!("Cannot install logger: {}", e)))
+}
+
+/// Parse a level such as `info`, or `EnvFilter` directives such as `info,dapp=debug`.
+pub fn filter(level: &str) -> Result<EnvFilter, LCoreError> {
+    EnvFilter::try_new(level).map_err(|e| LCoreError::Config(format!("log level {:?} is invalid: {}", level, e)))
+}
+
+/// Wrap a value that may carry sensor data, decrypted payloads or keys.
+/// It is logged as `[redacted]` unless redaction was turned off.
+pub fn redacted<T: fmt::Display>(value: T) -> Redacted<T> {
+    Redacted(value)
+}
+
+pub struct Redacted<T>(T);
+
+impl<T: fmt::Display> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if REDACT.load(Ordering::Relaxed) {
+            f.write_str("[redacted]")
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction_and_filter() {
+        assert_eq!(redacted("secret reading").to_string(), "[redacted]");
+        assert!(filter("info,dapp=debug").is_ok());
+        assert!(filter("info,dapp=loud").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+// lcore-node/src/metrics.rs
+//
+// Prometheus metrics, rendered in the text exposition format on `/metrics`
+
+use crate::health::HealthState;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Process-wide metrics. Handlers record into this directly; the values
+/// only feed `/metrics` and never influence rollup state.
+pub static METRICS: Metrics = Metrics::new();
+
+/// Upper bounds, in seconds, of the encryption timing buckets
+const TIMING_BUCKETS: [f64; 8] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 1.0];
+
+#[derive(Debug)]
+pub struct Metrics {
+    /// Finished requests by (request type, status)
+    inputs: Mutex<BTreeMap<(String, String), u64>>,
+    /// Failed device signature checks by what was being verified
+    signature_failures: Mutex<BTreeMap<&'static str, u64>>,
+    encrypt: Histogram,
+    decrypt: Histogram,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            inputs: Mutex::new(BTreeMap::new()),
+            signature_failures: Mutex::new(BTreeMap::new()),
+            encrypt: Histogram::new(),
+            decrypt: Histogram::new(),
+        }
+    }
+
+    /// Count a request the rollup loop finished with `status`.
+    pub fn record_input(&self, request_type: &str, status: &str) {
+        *self
+            .inputs
+            .lock()
+            .unwrap()
+            .entry((request_type.to_string(), status.to_string()))
+            .or_default() += 1;
+    }
+
+    /// Count a signature that failed to verify. `kind` is `reading`, `batch`,
+    /// `lifecycle`, or `read` for a consumer's inspect query.
+    pub fn record_signature_failure(&self, kind: &'static str) {
+        *self.signature_failures.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    /// Time spent on both encryption stages of one reading
+    pub fn observe_encrypt(&self, elapsed: Duration) {
+        self.encrypt.observe(elapsed);
+    }
+
+    /// Time spent on both decryption stages of one reading
+    pub fn observe_decrypt(&self, elapsed: Duration) {
+        self.decrypt.observe(elapsed);
+    }
+
+    /// Render every metric, plus the database gauges from the rollup loop's
+    /// last snapshot in `health`.
+    pub fn render(&self, health: &HealthState) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "lcore_inputs_total", "counter", "Rollup requests processed, by request type and finish status");
+        for ((request_type, status), count) in self.inputs.lock().unwrap().iter() {
+            let _ = writeln!(out, "lcore_inputs_total{{type=\"{}\",status=\"{}\"}} {}", request_type, status, count);
+        }
+
+        header(&mut out, "lcore_signature_verification_failures_total", "counter", "Device and reader signatures that failed to verify");
+        for (kind, count) in self.signature_failures.lock().unwrap().iter() {
+            let _ = writeln!(out, "lcore_signature_verification_failures_total{{kind=\"{}\"}} {}", kind, count);
+        }
+
+        self.encrypt.render(&mut out, "lcore_encrypt_duration_seconds", "Time to encrypt one reading with both stages");
+        self.decrypt.render(&mut out, "lcore_decrypt_duration_seconds", "Time to decrypt one reading with both stages");
+
+        if let Some(index) = health.last_input_index() {
+            header(&mut out, "lcore_last_input_index", "gauge", "Index of the last advance input processed");
+            let _ = writeln!(out, "lcore_last_input_index {}", index);
+        }
+
+        if let Some(database) = health.database() {
+            header(&mut out, "lcore_database_size_bytes", "gauge", "Size of the SQLite database");
+            let _ = writeln!(out, "lcore_database_size_bytes {}", database.size_bytes);
+
+            header(&mut out, "lcore_database_rows", "gauge", "Rows per database table");
+            for (table, count) in &database.row_counts {
+                let _ = writeln!(out, "lcore_database_rows{{table=\"{}\"}} {}", table, count);
+            }
+        }
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Cumulative histogram over `TIMING_BUCKETS`
+#[derive(Debug)]
+struct Histogram {
+    buckets: [AtomicU64; TIMING_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; TIMING_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(TIMING_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, "histogram", help);
+        for (bucket, bound) in self.buckets.iter().zip(TIMING_BUCKETS) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket.load(Ordering::Relaxed));
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.record_input("advance_state", "accept");
+        metrics.record_input("advance_state", "accept");
+        metrics.record_input("inspect_state", "reject");
+        metrics.record_signature_failure("reading");
+        metrics.observe_encrypt(Duration::from_micros(300));
+
+        let health = HealthState::new(Duration::from_secs(30));
+        let before_snapshot = metrics.render(&health);
+        assert!(!before_snapshot.contains("lcore_database_rows"));
+
+        health.refresh(&Database::open(":memory:").unwrap(), Some(3));
+        let text = metrics.render(&health);
+        assert!(text.contains("lcore_inputs_total{type=\"advance_state\",status=\"accept\"} 2\n"));
+        assert!(text.contains("lcore_inputs_total{type=\"inspect_state\",status=\"reject\"} 1\n"));
+        assert!(text.contains("lcore_signature_verification_failures_total{kind=\"reading\"} 1\n"));
+        assert!(text.contains("lcore_encrypt_duration_seconds_bucket{le=\"0.0001\"} 0\n"));
+        assert!(text.contains("lcore_encrypt_duration_seconds_bucket{le=\"0.0005\"} 1\n"));
+        assert!(text.contains("lcore_encrypt_duration_seconds_count 1\n"));
+        assert!(text.contains("lcore_decrypt_duration_seconds_count 0\n"));
+        assert!(text.contains("lcore_last_input_index 3\n"));
+        assert!(text.contains("lcore_database_rows{table=\"devices\"} 0\n"));
+        assert!(text.contains("# TYPE lcore_database_size_bytes gauge\n"));
+    }
+}
@@ -0,0 +1,173 @@
+// lcore-node/src/payloads.rs
+//
+// JSON payloads of the advance actions, and the decrypted reading inspect reports
+
+use crate::rules::Comparator;
+use serde::{Deserialize, Serialize};
+
+/// Reading returned by the `get_latest` inspect query
+#[derive(Deserialize, Serialize)]
+pub struct DecryptedSensorData {
+    pub id: i32,
+    pub device_id: String,
+    pub decrypted_payload: String,
+    pub timestamp: String,
+}
+
+/// Payload for `register`
+#[derive(Deserialize)]
+pub struct RegisterPayload {
+    pub device_id: String,
+    pub did_document: String,
+    pub public_key: String,
+    /// Gateway addresses allowed to submit for this device; empty = any sender
+    #[serde(default)]
+    pub authorized_senders: Vec<String>,
+    /// Registered device type whose schema the readings must satisfy
+    #[serde(default)]
+    pub device_type: Option<String>,
+}
+
+/// Payload for `register_schema`; `schema` follows `schema::ReadingSchema`
+#[derive(Deserialize)]
+pub struct SchemaPayload {
+    pub device_type: String,
+    pub schema: serde_json::Value,
+}
+
+/// Payload for `submit`, and one item of `submit_batch`.
+/// `jws` signs `device_auth::reading_message` with the device's current key.
+#[derive(Deserialize)]
+pub struct DataPayload {
+    pub device_id: String,
+    #[serde(default)]
+    pub jws: String,
+    pub data: String, // Hex-encoded hex string
+}
+
+/// Many readings uploaded by a gateway in one input.
+///
+/// Each item carries its own `jws`, or `root_jws` signs
+/// `device_auth::batch_message` over the `batch::merkle_root` of all items
+/// with the key of the single device the batch belongs to. Items take consecutive message counters in order.
+#[derive(Deserialize)]
+pub struct BatchPayload {
+    pub items: Vec<DataPayload>,
+    #[serde(default)]
+    pub root_jws: Option<String>,
+}
+
+/// Payload for `add_rule`; set exactly one of `device_id` / `device_type`
+#[derive(Deserialize)]
+pub struct AddRulePayload {
+    #[serde(default)]
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub device_type: Option<String>,
+    pub field: String,
+    pub comparator: Comparator,
+    pub threshold: f64,
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// Stake slashed on each trigger, base units as a decimal string
+    #[serde(default)]
+    pub slash_amount: Option<String>,
+}
+
+/// Payload for `remove_rule`
+#[derive(Deserialize)]
+pub struct RemoveRulePayload {
+    pub rule_id: i64,
+}
+
+/// Payload shared by `update_did`, `rotate_key`, `deactivate` and `reactivate`.
+/// `jws` signs `device_auth::lifecycle_message` with the device's current key;
+/// it may be left empty when the input is sent by the device owner or an admin.
+#[derive(Deserialize)]
+pub struct LifecyclePayload {
+    pub device_id: String,
+    #[serde(default)]
+    pub jws: String,
+    #[serde(default)]
+    pub did_document: Option<String>,
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+/// Payload for `grant_access` and `revoke_access`
+#[derive(Deserialize)]
+pub struct AccessPayload {
+    pub device_id: String,
+    pub grantee: String,
+}
+
+/// Payload for `set_access_price`; without `period_seconds` the price is per reading
+#[derive(Deserialize)]
+pub struct AccessPricePayload {
+    pub device_id: String,
+    /// `ether` or an ERC-20 token address
+    pub asset: String,
+    /// Base units as a decimal string, since amounts exceed JSON-safe integers
+    pub price: String,
+    #[serde(default)]
+    pub period_seconds: Option<u64>,
+}
+
+/// Payload for `purchase_access`: number of readings or periods to buy
+#[derive(Deserialize)]
+pub struct PurchaseAccessPayload {
+    pub device_id: String,
+    pub quantity: u64,
+}
+
+/// Payload for `set_reader_key`: the public JWK that signs the sender's inspect queries
+#[derive(Deserialize)]
+pub struct ReaderKeyPayload {
+    pub public_key: String,
+}
+
+/// Payload for `publish_analytics`; without `time_window` every window of the metric is published
+#[derive(Deserialize)]
+pub struct PublishAnalyticsPayload {
+    pub device_id: String,
+    pub metric_type: String,
+    #[serde(default)]
+    pub time_window: Option<String>,
+}
+
+/// Payload for `delete_device`
+#[derive(Deserialize)]
+pub struct DeletePayload {
+    pub device_id: String,
+}
+
+/// Payload for `release_stake`, by the owner of a deleted device's stake
+#[derive(Deserialize)]
+pub struct ReleaseStakePayload {
+    pub device_id: String,
+}
+
+/// Every JSON or CBOR input: an action name and its payload
+#[derive(Deserialize)]
+pub struct WrappedPayload {
+    pub action: String,
+    pub payload: serde_json::Value,
+}
+
+/// Payload for `slash`; without `amount` the whole remaining stake is slashed
+#[derive(Deserialize)]
+pub struct SlashPayload {
+    pub device_id: String,
+    #[serde(default)]
+    pub amount: Option<String>,
+    pub reason: String,
+}
+
+/// Payload for `withdraw`: move funds from the ledger back to L1
+#[derive(Deserialize)]
+pub struct WithdrawPayload {
+    /// `ether` or an ERC-20 token address
+    pub asset: String,
+    /// Base units as a decimal string
+    pub amount: String,
+}
@@ -0,0 +1,83 @@
+// lcore-node/src/permissions.rs
+//
+// Ownership and admin roles for advance actions
+
+use crate::database::DeviceRow;
+use crate::error::LCoreError;
+use crate::rollup::normalize_address;
+
+/// Who may act on a device, decided from the L1 `msg_sender` of the input.
+/// Admins are configured in the machine image, so the set is fixed at genesis.
+#[derive(Debug, Clone, Default)]
+pub struct Permissions {
+    admins: Vec<String>,
+}
+
+impl Permissions {
+    /// Build the permission set from a list of admin addresses.
+    pub fn new(admins: &[String]) -> Result<Self, LCoreError> {
+        let admins = admins
+            .iter()
+            .map(|address| normalize_address(address))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { admins })
+    }
+
+    /// Whether `sender` holds the dApp admin role.
+    pub fn is_admin(&self, sender: &str) -> bool {
+        self.admins.iter().any(|admin| admin == sender)
+    }
+
+    /// Whether `sender` owns the device.
+    pub fn is_owner(&self, sender: &str, device: &DeviceRow) -> bool {
+        device.owner == sender
+    }
+
+    /// Owners and admins manage a device: lifecycle actions without a device
+    /// signature, access grants and prices, reading its data without a grant,
+    /// publishing its analytics and deleting it.
+    pub fn can_manage(&self, sender: &str, device: &DeviceRow) -> bool {
+        self.is_owner(sender, device) || self.is_admin(sender)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DeviceStatus;
+
+    const OWNER: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+    const ADMIN: &str = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+    const STRANGER: &str = "0x3c44cdddb6a900fa2b585dd299e03d12fa4293bc";
+
+    fn device() -> DeviceRow {
+        DeviceRow {
+            id: "did:example:123".to_string(),
+            did_document: "{}".to_string(),
+            public_key: "{}".to_string(),
+            status: DeviceStatus::Active,
+            lifecycle_nonce: 0,
+            created_at: "2024-01-01T00:00:00+00:00".to_string(),
+            input_index: 0,
+            msg_sender: OWNER.to_string(),
+            owner: OWNER.to_string(),
+            device_type: None,
+        }
+    }
+
+    #[test]
+    fn test_owner_and_admin_can_manage() {
+        let permissions = Permissions::new(&["0x70997970C51812dc3A010C7d01b50e0d17dc79C8".to_string()]).unwrap();
+        let device = device();
+
+        assert!(permissions.can_manage(OWNER, &device));
+        assert!(permissions.can_manage(ADMIN, &device));
+        assert!(!permissions.can_manage(STRANGER, &device));
+        assert!(!permissions.is_admin(OWNER));
+    }
+
+    #[test]
+    fn test_invalid_admin_address() {
+        assert!(Permissions::new(&["not-an-address".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+// lcore-node/src/portals.rs
+//
+// Deposits relayed by the Ether and ERC-20 portals, and the dApp address relay
+
+use crate::error::LCoreError;
+use crate::rollup::normalize_address;
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use std::fmt;
+
+/// An asset held in the internal ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Asset {
+    Ether,
+    /// ERC-20 token, by lowercased contract address
+    Erc20(String),
+}
+
+impl Asset {
+    /// Ledger key: `ether` or the token address
+    pub fn as_str(&self) -> &str {
+        match self {
+            Asset::Ether => "ether",
+            Asset::Erc20(token) => token,
+        }
+    }
+
+    /// Parse a ledger key back into an asset
+    pub fn parse(asset: &str) -> Result<Self, LCoreError> {
+        match asset {
+            "ether" => Ok(Asset::Ether),
+            token => Ok(Asset::Erc20(normalize_address(token)?)),
+        }
+    }
+}
+
+impl ToSql for Asset {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Asset {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Asset::parse(value.as_str()?).map_err(|e| rusqlite::types::FromSqlError::Other(e.to_string().into()))
+    }
+}
+
+/// A uint256 relayed by a portal, as its 32 big-endian bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uint256(pub [u8; 32]);
+
+impl Uint256 {
+    /// The value, when it fits the ledger's 128 bits
+    pub fn to_u128(&self) -> Option<u128> {
+        if self.0[..16].iter().any(|byte| *byte != 0) {
+            return None;
+        }
+        let mut low = [0u8; 16];
+        low.copy_from_slice(&self.0[16..]);
+        Some(u128::from_be_bytes(low))
+    }
+}
+
+impl From<u128> for Uint256 {
+    fn from(value: u128) -> Self {
+        let mut word = [0u8; 32];
+        word[16..].copy_from_slice(&value.to_be_bytes());
+        Self(word)
+    }
+}
+
+/// Decimal, like every amount the ledger stores
+impl fmt::Display for Uint256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut value = self.0;
+        let mut digits = Vec::new();
+        while value.iter().any(|byte| *byte != 0) {
+            let mut remainder = 0u32;
+            for byte in value.iter_mut() {
+                let acc = (remainder << 8) | u32::from(*byte);
+                *byte = (acc / 10) as u8;
+                remainder = acc % 10;
+            }
+            digits.push(char::from(b'0' + remainder as u8));
+        }
+        if digits.is_empty() {
+            digits.push('0');
+        }
+        f.write_str(&digits.iter().rev().collect::<String>())
+    }
+}
+
+/// A decoded portal deposit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deposit {
+    pub asset: Asset,
+    pub depositor: String,
+    /// As relayed; amounts above 128 bits are held unclaimed rather than credited
+    pub amount: Uint256,
+    /// Free-form data the depositor attached
+    pub exec_layer_data: Vec<u8>,
+}
+
+/// Which portal relayed an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortalKind {
+    Ether,
+    Erc20,
+}
+
+/// Portal contract addresses the dApp accepts deposits from, and the relay
+/// that tells it its own address. An unset portal disables its deposits.
+#[derive(Debug, Clone, Default)]
+pub struct Portals {
+    ether_portal: Option<String>,
+    erc20_portal: Option<String>,
+    dapp_address_relay: Option<String>,
+}
+
+impl Portals {
+    pub fn new(ether_portal: Option<&str>, erc20_portal: Option<&str>) -> Result<Self, LCoreError> {
+        Ok(Self {
+            ether_portal: ether_portal.map(normalize_address).transpose()?,
+            erc20_portal: erc20_portal.map(normalize_address).transpose()?,
+            dapp_address_relay: None,
+        })
+    }
+
+    /// Accept the dApp's own address from `relay`.
+    pub fn with_dapp_address_relay(mut self, relay: &str) -> Result<Self, LCoreError> {
+        self.dapp_address_relay = Some(normalize_address(relay)?);
+        Ok(self)
+    }
+
+    /// Whether `msg_sender` is the DAppAddressRelay
+    pub fn is_dapp_address_relay(&self, msg_sender: &str) -> bool {
+        self.dapp_address_relay.as_deref() == Some(msg_sender)
+    }
+
+    /// The portal `msg_sender` is, if any. Only portal inputs are deposits;
+    /// everything else is a JSON/CBOR action.
+    pub fn portal_kind(&self, msg_sender: &str) -> Option<PortalKind> {
+        if self.ether_portal.as_deref() == Some(msg_sender) {
+            Some(PortalKind::Ether)
+        } else if self.erc20_portal.as_deref() == Some(msg_sender) {
+            Some(PortalKind::Erc20)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decode a DAppAddressRelay payload: the dApp's 20-byte address.
+pub fn decode_dapp_address(payload: &[u8]) -> Result<String, LCoreError> {
+    if payload.len() != 20 {
+        return Err(LCoreError::InvalidInput(format!("dApp address must be 20 bytes, got {}", payload.len())));
+    }
+    Ok(packed_address(payload))
+}
+
+/// Decode a portal payload. Returns `None` for an ERC-20 deposit whose token
+/// transfer failed, since nothing reached the dApp.
+pub fn decode_deposit(kind: PortalKind, payload: &[u8]) -> Result<Option<Deposit>, LCoreError> {
+    match kind {
+        PortalKind::Ether => decode_ether_deposit(payload).map(Some),
+        PortalKind::Erc20 => decode_erc20_deposit(payload),
+    }
+}
+
+/// `abi.encodePacked(address sender, uint256 value, bytes execLayerData)`
+fn decode_ether_deposit(payload: &[u8]) -> Result<Deposit, LCoreError> {
+    if payload.len() < 52 {
+        return Err(LCoreError::InvalidInput(format!("Ether deposit too short: {} bytes", payload.len())));
+    }
+    Ok(Deposit {
+        asset: Asset::Ether,
+        depositor: packed_address(&payload[..20]),
+        amount: packed_uint(&payload[20..52]),
+        exec_layer_data: payload[52..].to_vec(),
+    })
+}
+
+/// `abi.encodePacked(bool success, address token, address sender, uint256 amount, bytes execLayerData)`
+fn decode_erc20_deposit(payload: &[u8]) -> Result<Option<Deposit>, LCoreError> {
+    if payload.len() < 73 {
+        return Err(LCoreError::InvalidInput(format!("ERC-20 deposit too short: {} bytes", payload.len())));
+    }
+    if payload[0] != 1 {
+        return Ok(None);
+    }
+    Ok(Some(Deposit {
+        asset: Asset::Erc20(packed_address(&payload[1..21])),
+        depositor: packed_address(&payload[21..41]),
+        amount: packed_uint(&payload[41..73]),
+        exec_layer_data: payload[73..].to_vec(),
+    }))
+}
+
+fn packed_address(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn packed_uint(word: &[u8]) -> Uint256 {
+    let mut value = [0u8; 32];
+    value.copy_from_slice(&word[..32]);
+    Uint256(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETHER_PORTAL: &str = "0xF817490F242Ec5b690491e34CE1A446C5a99127E";
+    const ERC20_PORTAL: &str = "0xC11711CED3b1941f29DDA91c407876D0138595ad";
+    const TOKEN: &str = "5fbdb2315678afecb367f032d93f642f64180aa3";
+    const DEPOSITOR: &str = "f39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+    fn word(value: u128) -> Vec<u8> {
+        let mut word = vec![0u8; 16];
+        word.extend_from_slice(&value.to_be_bytes());
+        word
+    }
+
+    #[test]
+    fn test_portal_kind() {
+        let portals = Portals::new(Some(ETHER_PORTAL), Some(ERC20_PORTAL)).unwrap();
+        assert_eq!(portals.portal_kind(&ETHER_PORTAL.to_lowercase()), Some(PortalKind::Ether));
+        assert_eq!(portals.portal_kind(&ERC20_PORTAL.to_lowercase()), Some(PortalKind::Erc20));
+        assert_eq!(portals.portal_kind(&format!("0x{}", DEPOSITOR)), None);
+        assert_eq!(Portals::default().portal_kind(&ETHER_PORTAL.to_lowercase()), None);
+
+        let relay = "0xBe093D2660C578E4840b33B102468b5E4C20BDc4";
+        let portals = portals.with_dapp_address_relay(relay).unwrap();
+        assert!(portals.is_dapp_address_relay(&relay.to_lowercase()));
+        assert_eq!(portals.portal_kind(&relay.to_lowercase()), None);
+    }
+
+    #[test]
+    fn test_decode_dapp_address() {
+        let address = hex::decode("243e9f2ee6af8f1c0783ff10452cc1486820552c").unwrap();
+        assert_eq!(decode_dapp_address(&address).unwrap(), "0x243e9f2ee6af8f1c0783ff10452cc1486820552c");
+        assert!(decode_dapp_address(&address[..19]).is_err());
+    }
+
+    #[test]
+    fn test_decode_ether_deposit() {
+        let mut payload = hex::decode(DEPOSITOR).unwrap();
+        payload.extend(word(1_000_000_000_000_000_000));
+        payload.extend(b"stake");
+
+        let deposit = decode_deposit(PortalKind::Ether, &payload).unwrap().unwrap();
+        assert_eq!(deposit.asset, Asset::Ether);
+        assert_eq!(deposit.depositor, format!("0x{}", DEPOSITOR));
+        assert_eq!(deposit.amount, Uint256::from(1_000_000_000_000_000_000));
+        assert_eq!(deposit.exec_layer_data, b"stake");
+        assert!(decode_deposit(PortalKind::Ether, &payload[..40]).is_err());
+    }
+
+    #[test]
+    fn test_decode_erc20_deposit() {
+        let mut payload = vec![1u8];
+        payload.extend(hex::decode(TOKEN).unwrap());
+        payload.extend(hex::decode(DEPOSITOR).unwrap());
+        payload.extend(word(250));
+
+        let deposit = decode_deposit(PortalKind::Erc20, &payload).unwrap().unwrap();
+        assert_eq!(deposit.asset, Asset::Erc20(format!("0x{}", TOKEN)));
+        assert_eq!(deposit.amount, Uint256::from(250));
+        assert!(deposit.exec_layer_data.is_empty());
+
+        payload[0] = 0;
+        assert_eq!(decode_deposit(PortalKind::Erc20, &payload).unwrap(), None);
+
+        // Amounts beyond the ledger's 128 bits still decode
+        let mut huge = payload.clone();
+        huge[0] = 1;
+        huge[41] = 1;
+        let deposit = decode_deposit(PortalKind::Erc20, &huge).unwrap().unwrap();
+        assert_eq!(deposit.amount.to_u128(), None);
+        assert_eq!(
+            deposit.amount.to_string(),
+            "452312848583266388373324160190187140051835877600158453279131187530910662906"
+        );
+    }
+
+    #[test]
+    fn test_uint256_display() {
+        assert_eq!(Uint256::from(0).to_string(), "0");
+        assert_eq!(Uint256::from(u128::MAX).to_string(), u128::MAX.to_string());
+        assert_eq!(
+            Uint256([0xff; 32]).to_string(),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+    }
+}
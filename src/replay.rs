@@ -0,0 +1,199 @@
+// lcore-node/src/replay.rs
+//
+// Input logs for the `replay` binary: one JSON object per line, either a
+// rollup request exactly as `/finish` returns it, or an `InputAdded` event
+// exported from the InputBox (`eth_getLogs` / `cast logs --json` format)
+
+use crate::error::LCoreError;
+use crate::rollup::normalize_address;
+use json::{object, JsonValue};
+use serde::Deserialize;
+
+/// `keccak256("InputAdded(address,uint256,address,bytes)")`
+pub const INPUT_ADDED_TOPIC: &str = "0x6aaa400068bf4ca337265e2a1e1e841f66b8597fd5b452fdc52a44bed28a0784";
+
+/// An `InputAdded` log. `blockTimestamp` is only returned by newer nodes, so
+/// exports from older ones need a `timestamp` field added.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InputAddedLog {
+    topics: Vec<String>,
+    data: String,
+    block_number: Quantity,
+    #[serde(alias = "timestamp")]
+    block_timestamp: Option<Quantity>,
+    #[serde(default)]
+    removed: bool,
+}
+
+/// A JSON-RPC quantity: `"0x1a"`, or a plain number in hand-written logs
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+    Number(u64),
+    Text(String),
+}
+
+impl Quantity {
+    fn value(&self, field: &str) -> Result<u64, LCoreError> {
+        match self {
+            Quantity::Number(value) => Ok(*value),
+            Quantity::Text(text) => match text.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => text.parse().ok(),
+            }
+            .ok_or_else(|| LCoreError::InvalidInput(format!("{} is not a quantity: {}", field, text))),
+        }
+    }
+}
+
+/// Read a log into the requests to replay, in order. Blank lines are
+/// skipped, as are events for other dApps when `dapp` is given and events
+/// dropped by a reorg.
+pub fn parse_log(contents: &str, dapp: Option<&str>) -> Result<Vec<JsonValue>, LCoreError> {
+    let dapp = dapp.map(normalize_address).transpose()?;
+    let mut requests = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = parse_line(line, dapp.as_deref())
+            .map_err(|e| LCoreError::InvalidInput(format!("line {}: {}", number + 1, e)))?;
+        requests.extend(request);
+    }
+    Ok(requests)
+}
+
+fn parse_line(line: &str, dapp: Option<&str>) -> Result<Option<JsonValue>, LCoreError> {
+    let value = json::parse(line).map_err(|e| LCoreError::InvalidInput(e.to_string()))?;
+    if value.has_key("request_type") {
+        return Ok(Some(value));
+    }
+    if !value.has_key("topics") {
+        return Err(LCoreError::InvalidInput("neither a rollup request nor an event log".to_string()));
+    }
+
+    let log: InputAddedLog = serde_json::from_str(line)?;
+    if log.removed {
+        return Ok(None);
+    }
+    let [topic, log_dapp, input_index] = &log.topics[..] else {
+        return Err(LCoreError::InvalidInput(format!("expected 3 topics, got {}", log.topics.len())));
+    };
+    if !topic.eq_ignore_ascii_case(INPUT_ADDED_TOPIC) {
+        return Err(LCoreError::InvalidInput(format!("not an InputAdded event: {}", topic)));
+    }
+    if dapp.is_some_and(|dapp| word_address(log_dapp).ok().as_deref() != Some(dapp)) {
+        return Ok(None);
+    }
+
+    let input_index = word_u64(&hex::decode(input_index.trim_start_matches("0x"))?, "inputIndex")?;
+    let timestamp = log
+        .block_timestamp
+        .ok_or_else(|| LCoreError::InvalidInput("event has no blockTimestamp".to_string()))?
+        .value("blockTimestamp")?;
+    let (sender, input) = decode_input_added(&hex::decode(log.data.trim_start_matches("0x"))?)?;
+
+    Ok(Some(object! {
+        "request_type" => "advance_state",
+        "data" => object! {
+            "metadata" => object! {
+                "msg_sender" => sender,
+                "epoch_index" => 0,
+                "input_index" => input_index,
+                "block_number" => log.block_number.value("blockNumber")?,
+                "timestamp" => timestamp,
+            },
+            "payload" => format!("0x{}", hex::encode(input)),
+        },
+    }))
+}
+
+/// The 20-byte address in a 32-byte ABI word
+fn word_address(word: &str) -> Result<String, LCoreError> {
+    let hex = word.trim_start_matches("0x");
+    if hex.len() != 64 || hex[..24].bytes().any(|c| c != b'0') {
+        return Err(LCoreError::InvalidInput(format!("not an address word: {}", word)));
+    }
+    normalize_address(&format!("0x{}", &hex[24..]))
+}
+
+/// A 32-byte ABI word holding an unsigned integer that fits in 64 bits
+fn word_u64(word: &[u8], field: &str) -> Result<u64, LCoreError> {
+    if word.len() != 32 || word[..24].iter().any(|byte| *byte != 0) {
+        return Err(LCoreError::InvalidInput(format!("{} is out of range", field)));
+    }
+    let mut low = [0u8; 8];
+    low.copy_from_slice(&word[24..]);
+    Ok(u64::from_be_bytes(low))
+}
+
+/// `abi.encode(address sender, bytes input)`, the event's unindexed data
+fn decode_input_added(data: &[u8]) -> Result<(String, Vec<u8>), LCoreError> {
+    let truncated = || LCoreError::InvalidInput("InputAdded data is truncated".to_string());
+    let word = |offset: usize| offset.checked_add(32).and_then(|end| data.get(offset..end)).ok_or_else(truncated);
+
+    let sender = word_address(&hex::encode(word(0)?))?;
+    let offset = usize::try_from(word_u64(word(32)?, "input offset")?).map_err(|_| truncated())?;
+    let length = usize::try_from(word_u64(word(offset)?, "input length")?).map_err(|_| truncated())?;
+    let input = data
+        .get(offset + 32..)
+        .and_then(|rest| rest.get(..length))
+        .ok_or_else(truncated)?;
+    Ok((sender, input.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::{self, Token};
+
+    const DAPP: &str = "0x70ac08179605af2d9e75782b8decdd3c22aa4d0c";
+    const SENDER: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+    fn input_added(dapp: &str, input_index: u64, input: &[u8]) -> String {
+        let data = abi::encode(&[Token::address(SENDER).unwrap(), Token::Bytes(input.to_vec())]);
+        serde_json::json!({
+            "address": "0xec2819623edff798c749317c454e19073af094f4",
+            "topics": [
+                INPUT_ADDED_TOPIC,
+                format!("0x{:0>64}", dapp.trim_start_matches("0x")),
+                format!("0x{:064x}", input_index),
+            ],
+            "data": format!("0x{}", hex::encode(data)),
+            "blockNumber": "0x1b",
+            "blockTimestamp": "0x65920080",
+            "removed": false,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let request = r#"{"request_type":"inspect_state","data":{"payload":"0x"}}"#;
+        let other_dapp = "0x0000000000000000000000000000000000000001";
+        let log = [
+            input_added(DAPP, 7, b"{\"action\":\"register\"}"),
+            String::new(),
+            request.to_string(),
+            input_added(other_dapp, 0, b"ignored"),
+        ]
+        .join("\n");
+
+        let requests = parse_log(&log, Some(DAPP)).unwrap();
+        assert_eq!(requests.len(), 2);
+        let metadata = &requests[0]["data"]["metadata"];
+        assert_eq!(metadata["msg_sender"], SENDER);
+        assert_eq!(metadata["input_index"], 7);
+        assert_eq!(metadata["block_number"], 27);
+        assert_eq!(metadata["timestamp"], 1_704_067_200);
+        assert_eq!(requests[0]["data"]["payload"], format!("0x{}", hex::encode(b"{\"action\":\"register\"}")));
+        assert_eq!(requests[1]["request_type"], "inspect_state");
+
+        // Without a dApp filter every event is replayed
+        assert_eq!(parse_log(&log, None).unwrap().len(), 3);
+
+        let error = parse_log("{\"topics\":[]}", None).unwrap_err();
+        assert!(error.to_string().contains("line 1"));
+    }
+}
@@ -0,0 +1,185 @@
+// lcore-node/src/rollup.rs
+//
+// Cartesi rollup request metadata and output helpers
+
+use crate::error::LCoreError;
+use crate::vouchers::Voucher;
+use json::{object, JsonValue};
+use serde::Deserialize;
+
+/// Metadata attached by the rollups node to every advance request.
+///
+/// These values are identical on every validator replaying the same input, so
+/// they are the only acceptable source of time and provenance inside the VM.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdvanceMetadata {
+    pub msg_sender: String,
+    #[serde(default)]
+    pub epoch_index: u64,
+    pub input_index: u64,
+    pub block_number: u64,
+    pub timestamp: u64,
+}
+
+impl AdvanceMetadata {
+    /// Parse the `data.metadata` object of an advance request.
+    pub fn from_request(request: &JsonValue) -> Result<Self, LCoreError> {
+        let metadata = &request["data"]["metadata"];
+        if metadata.is_null() {
+            return Err(LCoreError::Protocol("Missing metadata".to_string()));
+        }
+
+        let mut parsed: AdvanceMetadata = serde_json::from_str(&metadata.dump())?;
+        // Addresses are compared as strings, so normalise the checksum casing away.
+        parsed.msg_sender = normalize_address(&parsed.msg_sender)?;
+        Ok(parsed)
+    }
+
+    /// Block timestamp of the input as an RFC 3339 string.
+    pub fn timestamp_rfc3339(&self) -> Result<String, LCoreError> {
+        let secs = i64::try_from(self.timestamp)
+            .map_err(|_| LCoreError::InvalidInput(format!("Timestamp out of range: {}", self.timestamp)))?;
+        chrono::DateTime::from_timestamp(secs, 0)
+            .map(|dt| dt.to_rfc3339())
+            .ok_or_else(|| LCoreError::InvalidInput(format!("Timestamp out of range: {}", self.timestamp)))
+    }
+}
+
+#[cfg(test)]
+impl AdvanceMetadata {
+    /// Metadata of a test input, with the block number following the input index
+    pub(crate) fn for_test(msg_sender: &str, input_index: u64, timestamp: u64) -> Self {
+        Self {
+            msg_sender: msg_sender.to_string(),
+            epoch_index: 0,
+            input_index,
+            block_number: input_index,
+            timestamp,
+        }
+    }
+}
+
+/// Validate a `0x`-prefixed 20-byte hex address and return it lowercased.
+pub fn normalize_address(address: &str) -> Result<String, LCoreError> {
+    let hex_part = address
+        .strip_prefix("0x")
+        .ok_or_else(|| LCoreError::InvalidInput(format!("Address must be 0x-prefixed: {}", address)))?;
+    if hex_part.len() != 40 || !hex_part.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(LCoreError::InvalidInput(format!("Invalid address: {}", address)));
+    }
+    Ok(address.to_lowercase())
+}
+
+/// Send a report to the rollup HTTP server. Reports are kept even when the
+/// input is rejected, which makes them the channel for explaining rejections.
+pub async fn send_report(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    payload: &[u8],
+) -> Result<(), LCoreError> {
+    post_output(client, server_addr, "report", hex_payload(payload)).await
+}
+
+/// Send a notice to the rollup HTTP server. Notices can be proven on L1
+/// against the epoch's output hashes once the epoch is finalized.
+pub async fn send_notice(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    payload: &[u8],
+) -> Result<(), LCoreError> {
+    post_output(client, server_addr, "notice", hex_payload(payload)).await
+}
+
+/// Send a voucher to the rollup HTTP server. Once its epoch is finalized
+/// anyone can execute it, making the dApp contract perform the call.
+pub async fn send_voucher(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    voucher: &Voucher,
+) -> Result<(), LCoreError> {
+    let mut body = hex_payload(&voucher.payload);
+    body["destination"] = voucher.destination.as_str().into();
+    post_output(client, server_addr, "voucher", body).await
+}
+
+/// Tell the rollup HTTP server the request could not be processed. The
+/// rollups node treats this as a failure of the machine, not of the input.
+pub async fn send_exception(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    payload: &[u8],
+) -> Result<(), LCoreError> {
+    post_output(client, server_addr, "exception", hex_payload(payload)).await
+}
+
+fn hex_payload(payload: &[u8]) -> JsonValue {
+    object! {"payload" => format!("0x{}", hex::encode(payload))}
+}
+
+/// POST an output body to `/<kind>` on the rollup HTTP server.
+async fn post_output(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    server_addr: &str,
+    kind: &str,
+    body: JsonValue,
+) -> Result<(), LCoreError> {
+    let request = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .uri(format!("{}/{}", server_addr, kind))
+        .body(hyper::Body::from(body.dump()))
+        .map_err(|e| LCoreError::Rollup(format!("Failed to build {} request: {}", kind, e)))?;
+    let response = client
+        .request(request)
+        .await
+        .map_err(|e| LCoreError::Rollup(format!("Failed to send {}: {}", kind, e)))?;
+
+    if !response.status().is_success() {
+        return Err(LCoreError::Rollup(format!("{} rejected with status {}", kind, response.status())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_from_request() {
+        let request = json::parse(r#"{
+            "request_type": "advance_state",
+            "data": {
+                "metadata": {
+                    "msg_sender": "0xF39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+                    "epoch_index": 0,
+                    "input_index": 7,
+                    "block_number": 1717,
+                    "timestamp": 1704067200
+                },
+                "payload": "0x"
+            }
+        }"#).unwrap();
+
+        let metadata = AdvanceMetadata::from_request(&request).expect("metadata should parse");
+        assert_eq!(metadata.msg_sender, "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+        assert_eq!(metadata.input_index, 7);
+        assert_eq!(metadata.block_number, 1717);
+        assert_eq!(metadata.timestamp_rfc3339().unwrap(), "2024-01-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_normalize_address() {
+        assert_eq!(
+            normalize_address("0xF39Fd6e51aad88F6F4ce6aB8827279cffFb92266").unwrap(),
+            "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266"
+        );
+        assert!(normalize_address("f39fd6e51aad88f6f4ce6ab8827279cfffb92266").is_err());
+        assert!(normalize_address("0x1234").is_err());
+    }
+
+    #[test]
+    fn test_metadata_missing() {
+        let request = json::parse(r#"{"data": {"payload": "0x"}}"#).unwrap();
+        assert!(AdvanceMetadata::from_request(&request).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+// lcore-node/src/rollup_stub.rs
+//
+// In-process stand-in for the rollup HTTP server, so the `/finish` loop can
+// be exercised end to end without a Cartesi rollups node. Requests are
+// scripted up front and every output the node sends is recorded against the
+// request that produced it.
+
+use crate::app::App;
+use crate::error::LCoreError;
+use crate::health::HealthState;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use json::{object, JsonValue};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Default sender of scripted advance requests
+pub const DEFAULT_SENDER: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+/// Block timestamp of the first scripted advance; later ones follow one second apart
+const GENESIS_TIMESTAMP: u64 = 1_704_067_200;
+/// How long `/finish` holds an idle node before answering 202, like the real long poll
+const IDLE_POLL: Duration = Duration::from_millis(50);
+
+/// An output the node sent while processing a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Notice(Vec<u8>),
+    Report(Vec<u8>),
+    Voucher { destination: String, payload: Vec<u8> },
+    Exception(Vec<u8>),
+}
+
+/// A request handed to the node, with its outputs and the status it finished with
+#[derive(Debug, Clone)]
+pub struct Processed {
+    pub request: JsonValue,
+    pub outputs: Vec<Output>,
+    /// `accept`, `reject`, or `exception` when the node raised one
+    pub status: String,
+}
+
+impl Processed {
+    /// Reports parsed as JSON, in the order they were sent
+    pub fn reports(&self) -> Vec<serde_json::Value> {
+        self.outputs
+            .iter()
+            .filter_map(|output| match output {
+                Output::Report(payload) => serde_json::from_slice(payload).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Notices parsed as JSON, in the order they were sent
+    pub fn notices(&self) -> Vec<serde_json::Value> {
+        self.outputs
+            .iter()
+            .filter_map(|output| match output {
+                Output::Notice(payload) => serde_json::from_slice(payload).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn vouchers(&self) -> Vec<(&str, &[u8])> {
+        self.outputs
+            .iter()
+            .filter_map(|output| match output {
+                Output::Voucher { destination, payload } => Some((destination.as_str(), payload.as_slice())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Script {
+    pending: VecDeque<JsonValue>,
+    /// The request the node is working on; outputs are attached to it
+    current: Option<Processed>,
+    processed: Vec<Processed>,
+    next_input_index: u64,
+    next_notice_index: u64,
+    next_voucher_index: u64,
+}
+
+impl Script {
+    /// Hand out the next queued request, which becomes the current one
+    fn start_next(&mut self) -> Option<JsonValue> {
+        let request = self.pending.pop_front()?;
+        self.current = Some(Processed { request: request.clone(), outputs: Vec::new(), status: String::new() });
+        Some(request)
+    }
+}
+
+struct Shared {
+    script: Mutex<Script>,
+    /// Bumped on every change so waiters can re-check the script
+    changed: watch::Sender<()>,
+}
+
+impl Shared {
+    fn update<T>(&self, f: impl FnOnce(&mut Script) -> T) -> T {
+        let result = f(&mut self.script.lock().unwrap());
+        self.changed.send_replace(());
+        result
+    }
+}
+
+/// The rollup HTTP API on an ephemeral local port; stops when dropped.
+pub struct RollupStub {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    server: JoinHandle<()>,
+}
+
+impl RollupStub {
+    /// Start serving `/finish`, `/notice`, `/report`, `/voucher` and `/exception` on 127.0.0.1.
+    pub async fn start() -> Result<Self, LCoreError> {
+        let shared = Arc::new(Shared { script: Mutex::new(Script::default()), changed: watch::channel(()).0 });
+
+        let service_state = shared.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let shared = service_state.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                    let shared = shared.clone();
+                    async move { Ok::<_, hyper::Error>(respond(req, &shared).await) }
+                }))
+            }
+        });
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .map_err(|e| LCoreError::Internal(format!("Rollup stub failed to bind: {}", e)))?
+            .serve(make_svc);
+        let addr = server.local_addr();
+        let server = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        Ok(Self { addr, shared, server })
+    }
+
+    /// Base URL to give `App::new` in place of `ROLLUP_HTTP_SERVER_URL`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Queue an advance request from `msg_sender`; returns its input index.
+    pub fn advance_from(&self, msg_sender: &str, payload: &[u8]) -> u64 {
+        self.shared.update(|script| {
+            let input_index = script.next_input_index;
+            script.next_input_index += 1;
+            script.pending.push_back(advance_request(msg_sender, input_index, payload));
+            input_index
+        })
+    }
+
+    /// Queue an advance request from [`DEFAULT_SENDER`]; returns its input index.
+    pub fn advance(&self, payload: &[u8]) -> u64 {
+        self.advance_from(DEFAULT_SENDER, payload)
+    }
+
+    /// Queue an inspect request; inspects carry no metadata.
+    pub fn inspect(&self, payload: &[u8]) {
+        self.push(object! {
+            "request_type" => "inspect_state",
+            "data" => object! { "payload" => hex_payload(payload) },
+        });
+    }
+
+    /// Queue a request exactly as given, for scripts recorded elsewhere.
+    pub fn push(&self, request: JsonValue) {
+        self.shared.update(|script| script.pending.push_back(request));
+    }
+
+    /// Requests the node has finished with, in order
+    pub fn processed(&self) -> Vec<Processed> {
+        self.shared.script.lock().unwrap().processed.clone()
+    }
+
+    /// Wait until every queued request has been finished: the node asked
+    /// for more work after the last one, or raised an exception.
+    pub async fn settled(&self) {
+        let mut changed = self.shared.changed.subscribe();
+        loop {
+            {
+                let script = self.shared.script.lock().unwrap();
+                let exception = script.processed.last().is_some_and(|processed| processed.status == "exception");
+                if exception || (script.pending.is_empty() && script.current.is_none()) {
+                    return;
+                }
+            }
+            if changed.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Run `app`'s rollup loop against this stub until the script is
+    /// exhausted and return what was processed.
+    pub async fn run(&self, app: &App) -> Result<Vec<Processed>, LCoreError> {
+        let health = HealthState::new(Duration::from_secs(30));
+        tokio::select! {
+            result = app.run(&health) => {
+                // The loop only stops on an exception, which the stub has recorded
+                if let Err(e) = result {
+                    if self.processed().last().is_none_or(|processed| processed.status != "exception") {
+                        return Err(e);
+                    }
+                }
+            }
+            _ = self.settled() => {}
+        }
+        Ok(self.processed())
+    }
+}
+
+impl Drop for RollupStub {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// An advance request as `/finish` hands it out, for input `input_index` from
+/// `msg_sender`. Block numbers and timestamps follow the input index.
+pub fn advance_request(msg_sender: &str, input_index: u64, payload: &[u8]) -> JsonValue {
+    object! {
+        "request_type" => "advance_state",
+        "data" => object! {
+            "metadata" => object! {
+                "msg_sender" => msg_sender,
+                "epoch_index" => 0,
+                "input_index" => input_index,
+                "block_number" => input_index,
+                "timestamp" => GENESIS_TIMESTAMP + input_index,
+            },
+            "payload" => hex_payload(payload),
+        },
+    }
+}
+
+fn hex_payload(payload: &[u8]) -> String {
+    format!("0x{}", hex::encode(payload))
+}
+
+async fn respond(req: Request<Body>, shared: &Shared) -> Response<Body> {
+    let (method, path) = (req.method().clone(), req.uri().path().to_string());
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return text_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let Some(body) = std::str::from_utf8(&body).ok().and_then(|body| json::parse(body).ok()) else {
+        return text_response(StatusCode::BAD_REQUEST, "body is not JSON");
+    };
+
+    match (method, path.as_str()) {
+        (Method::POST, "/finish") => finish(shared, &body).await,
+        (Method::POST, "/notice") => record(shared, &body, |script, payload| {
+            script.next_notice_index += 1;
+            (Output::Notice(payload), Some(script.next_notice_index - 1))
+        }),
+        (Method::POST, "/voucher") => {
+            let Some(destination) = body["destination"].as_str().map(str::to_string) else {
+                return text_response(StatusCode::BAD_REQUEST, "voucher has no destination");
+            };
+            record(shared, &body, |script, payload| {
+                script.next_voucher_index += 1;
+                (Output::Voucher { destination, payload }, Some(script.next_voucher_index - 1))
+            })
+        }
+        (Method::POST, "/report") => record(shared, &body, |_, payload| (Output::Report(payload), None)),
+        (Method::POST, "/exception") => {
+            let response = record(shared, &body, |_, payload| (Output::Exception(payload), None));
+            shared.update(|script| {
+                if let Some(mut processed) = script.current.take() {
+                    processed.status = "exception".to_string();
+                    script.processed.push(processed);
+                }
+            });
+            response
+        }
+        _ => text_response(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+/// Close the current request with the reported status and hand out the
+/// next one, holding the node for a moment when there is none.
+async fn finish(shared: &Shared, body: &JsonValue) -> Response<Body> {
+    let status = body["status"].as_str().unwrap_or_default().to_string();
+    let mut changed = shared.changed.subscribe();
+    let next = shared.update(|script| {
+        if let Some(mut processed) = script.current.take() {
+            if status == "reject" {
+                // A rejected input keeps only its reports, and the indices its
+                // notices and vouchers took are handed out again
+                processed.outputs.retain(|output| match output {
+                    Output::Notice(_) => {
+                        script.next_notice_index -= 1;
+                        false
+                    }
+                    Output::Voucher { .. } => {
+                        script.next_voucher_index -= 1;
+                        false
+                    }
+                    _ => true,
+                });
+            }
+            processed.status = status;
+            script.processed.push(processed);
+        }
+        script.start_next()
+    });
+    let next = match next {
+        Some(next) => Some(next),
+        None => {
+            let _ = tokio::time::timeout(IDLE_POLL, changed.changed()).await;
+            shared.update(Script::start_next)
+        }
+    };
+
+    match next {
+        Some(request) => json_response(StatusCode::OK, request.dump()),
+        None => Response::builder().status(StatusCode::ACCEPTED).body(Body::empty()).unwrap(),
+    }
+}
+
+/// Attach an output to the current request, as the real server does; outputs
+/// outside a request are refused.
+fn record(
+    shared: &Shared,
+    body: &JsonValue,
+    output: impl FnOnce(&mut Script, Vec<u8>) -> (Output, Option<u64>),
+) -> Response<Body> {
+    let payload = match body["payload"].as_str().map(|payload| hex::decode(payload.trim_start_matches("0x"))) {
+        Some(Ok(payload)) => payload,
+        _ => return text_response(StatusCode::BAD_REQUEST, "payload is not 0x-prefixed hex"),
+    };
+    let index = shared.update(|script| {
+        script.current.as_ref()?;
+        let (output, index) = output(script, payload);
+        script.current.as_mut()?.outputs.push(output);
+        Some(index)
+    });
+    match index {
+        Some(Some(index)) => json_response(StatusCode::OK, object! { "index" => index }.dump()),
+        Some(None) => Response::builder().status(StatusCode::OK).body(Body::empty()).unwrap(),
+        None => text_response(StatusCode::BAD_REQUEST, "no request is being processed"),
+    }
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn text_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder().status(status).body(Body::from(message.to_string())).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn post(client: &hyper::Client<hyper::client::HttpConnector>, stub: &RollupStub, path: &str, body: JsonValue) -> String {
+        let request = Request::post(format!("{}/{}", stub.url(), path)).body(Body::from(body.dump())).unwrap();
+        let response = client.request(request).await.unwrap();
+        String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reject_drops_notices_and_vouchers() {
+        let stub = RollupStub::start().await.unwrap();
+        let client = hyper::Client::new();
+        stub.advance(b"first");
+        stub.advance(b"second");
+
+        post(&client, &stub, "finish", object! { "status" => "accept" }).await;
+        let notice = post(&client, &stub, "notice", object! { "payload" => "0x01" }).await;
+        assert_eq!(json::parse(&notice).unwrap()["index"], 0);
+        let voucher = object! { "destination" => DEFAULT_SENDER, "payload" => "0x02" };
+        assert_eq!(json::parse(&post(&client, &stub, "voucher", voucher).await).unwrap()["index"], 0);
+        post(&client, &stub, "report", object! { "payload" => "0x03" }).await;
+
+        // The rejected input's indices go to the next one
+        post(&client, &stub, "finish", object! { "status" => "reject" }).await;
+        let notice = post(&client, &stub, "notice", object! { "payload" => "0x04" }).await;
+        assert_eq!(json::parse(&notice).unwrap()["index"], 0);
+        post(&client, &stub, "finish", object! { "status" => "accept" }).await;
+
+        let processed = stub.processed();
+        assert_eq!(processed[0].status, "reject");
+        assert!(matches!(processed[0].outputs.as_slice(), [Output::Report(payload)] if payload == &[3]));
+        assert!(matches!(processed[1].outputs.as_slice(), [Output::Notice(payload)] if payload == &[4]));
+    }
+}
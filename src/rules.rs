@@ -0,0 +1,214 @@
+// lcore-node/src/rules.rs
+//
+// Threshold rules evaluated on accepted readings
+
+use crate::analytics::numeric_fields;
+use crate::database::{AlertRow, Database, DeviceRow, RuleRow};
+use crate::error::LCoreError;
+use crate::rollup::AdvanceMetadata;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+/// How a reading is compared against a rule threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Comparator {
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+impl Comparator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparator::Gt => "gt",
+            Comparator::Gte => "gte",
+            Comparator::Lt => "lt",
+            Comparator::Lte => "lte",
+        }
+    }
+
+    pub fn parse(comparator: &str) -> Result<Self, LCoreError> {
+        match comparator {
+            "gt" => Ok(Comparator::Gt),
+            "gte" => Ok(Comparator::Gte),
+            "lt" => Ok(Comparator::Lt),
+            "lte" => Ok(Comparator::Lte),
+            other => Err(LCoreError::InvalidInput(format!("Unknown comparator: {}", other))),
+        }
+    }
+
+    /// Whether `value` breaches `threshold`
+    pub fn breached(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparator::Gt => value > threshold,
+            Comparator::Gte => value >= threshold,
+            Comparator::Lt => value < threshold,
+            Comparator::Lte => value <= threshold,
+        }
+    }
+
+    /// Whether `value` is back on the safe side of `threshold` by at least
+    /// `hysteresis`, so an active alert may clear without flapping. Clearing
+    /// is strict for the inclusive comparators, so a value at the threshold
+    /// never both breaches and clears.
+    pub fn cleared(&self, value: f64, threshold: f64, hysteresis: f64) -> bool {
+        match self {
+            Comparator::Gt => value <= threshold - hysteresis,
+            Comparator::Gte => value < threshold - hysteresis,
+            Comparator::Lt => value >= threshold + hysteresis,
+            Comparator::Lte => value > threshold + hysteresis,
+        }
+    }
+}
+
+impl ToSql for Comparator {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Comparator {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Comparator::parse(value.as_str()?).map_err(|e| FromSqlError::Other(e.to_string().into()))
+    }
+}
+
+/// Alert transition produced by a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertState {
+    Triggered,
+    Cleared,
+}
+
+impl AlertState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertState::Triggered => "triggered",
+            AlertState::Cleared => "cleared",
+        }
+    }
+}
+
+/// Evaluate every rule that applies to `device` against an accepted reading,
+/// recording alert transitions. A rule fires once when breached and stays
+/// quiet until the value has cleared the threshold by its hysteresis.
+pub fn evaluate(
+    db: &Database,
+    device: &DeviceRow,
+    data: &[u8],
+    metadata: &AdvanceMetadata,
+) -> Result<Vec<AlertRow>, LCoreError> {
+    let fields = numeric_fields(data);
+    if fields.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut alerts = Vec::new();
+    for rule in db.get_rules_for_device(&device.id, device.device_type.as_deref())? {
+        let value = match fields.iter().find(|(field, _)| *field == rule.field) {
+            Some((_, value)) => *value,
+            None => continue,
+        };
+
+        let active = db.get_rule_state(rule.id, &device.id)?;
+        let transition = if !active && rule.comparator.breached(value, rule.threshold) {
+            Some(AlertState::Triggered)
+        } else if active && rule.comparator.cleared(value, rule.threshold, rule.hysteresis) {
+            Some(AlertState::Cleared)
+        } else {
+            None
+        };
+
+        if let Some(state) = transition {
+            db.set_rule_state(rule.id, &device.id, state == AlertState::Triggered)?;
+            alerts.push(db.insert_alert(&rule, &device.id, value, state, metadata)?);
+        }
+    }
+
+    Ok(alerts)
+}
+
+/// Whether a rule definition is usable
+pub fn validate_rule(rule: &RuleRow) -> Result<(), LCoreError> {
+    if rule.device_id.is_some() == rule.device_type.is_some() {
+        return Err(LCoreError::InvalidInput("Rule must target exactly one of device_id or device_type".to_string()));
+    }
+    if rule.field.is_empty() {
+        return Err(LCoreError::InvalidInput("Rule field must not be empty".to_string()));
+    }
+    if !rule.threshold.is_finite() || !rule.hysteresis.is_finite() || rule.hysteresis < 0.0 {
+        return Err(LCoreError::InvalidInput("Rule threshold and hysteresis must be finite, hysteresis >= 0".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comparator_hysteresis() {
+        let gt = Comparator::Gt;
+        assert!(gt.breached(8.1, 8.0));
+        assert!(!gt.breached(8.0, 8.0));
+        assert!(!gt.cleared(7.8, 8.0, 0.5));
+        assert!(gt.cleared(7.5, 8.0, 0.5));
+
+        let lt = Comparator::Lt;
+        assert!(lt.breached(-1.0, 0.0));
+        assert!(lt.cleared(1.0, 0.0, 1.0));
+        assert_eq!(Comparator::parse("lte").unwrap(), Comparator::Lte);
+        assert!(Comparator::parse("eq").is_err());
+    }
+
+    #[test]
+    fn test_inclusive_comparator_at_threshold() {
+        let gte = Comparator::Gte;
+        assert!(gte.breached(8.0, 8.0));
+        assert!(!gte.cleared(8.0, 8.0, 0.0));
+        assert!(gte.cleared(7.9, 8.0, 0.0));
+
+        let lte = Comparator::Lte;
+        assert!(lte.breached(0.0, 0.0));
+        assert!(!lte.cleared(0.0, 0.0, 0.0));
+        assert!(lte.cleared(0.1, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_cold_chain_rule() {
+        let db = Database::open(":memory:").unwrap();
+        let mut metadata = AdvanceMetadata::for_test("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266", 1, 1_704_067_200);
+        db.insert_device("did:example:fridge", "{}", "{}", Some("temperature"), &[], &metadata).unwrap();
+        let device = db.get_device("did:example:fridge").unwrap().unwrap();
+
+        let rule = RuleRow {
+            id: 0,
+            device_id: None,
+            device_type: Some("temperature".to_string()),
+            field: "temperature".to_string(),
+            comparator: Comparator::Gt,
+            threshold: 8.0,
+            hysteresis: 0.5,
+            slash_amount: None,
+        };
+        validate_rule(&rule).unwrap();
+        db.insert_rule(&rule, &metadata).unwrap();
+
+        let mut states = Vec::new();
+        for (index, reading) in ["4.0", "8.5", "9.0", "7.8", "7.4", "8.2"].iter().enumerate() {
+            metadata.input_index = index as u64 + 2;
+            let data = format!(r#"{{"temperature": {}}}"#, reading);
+            for alert in evaluate(&db, &device, data.as_bytes(), &metadata).unwrap() {
+                states.push((alert.value, alert.state));
+            }
+        }
+        assert_eq!(states, vec![
+            (8.5, "triggered".to_string()),
+            (7.4, "cleared".to_string()),
+            (8.2, "triggered".to_string()),
+        ]);
+        assert_eq!(db.get_alerts("did:example:fridge").unwrap().len(), 3);
+    }
+}
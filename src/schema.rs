@@ -0,0 +1,177 @@
+// lcore-node/src/schema.rs
+//
+// Typed sensor reading schemas per device type
+
+use crate::error::LCoreError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Primitive type of a reading field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Number,
+    Integer,
+    String,
+    Boolean,
+}
+
+/// Constraints on one field of a reading
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldSpec {
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    #[serde(default = "default_required")]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+/// Shape of the JSON readings a device type submits, e.g.
+/// `{"fields": {"temperature": {"type": "number", "min": -273.15}}}`.
+///
+/// Fields are kept in a `BTreeMap` so validation errors are reported in the
+/// same order on every validator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadingSchema {
+    pub fields: BTreeMap<String, FieldSpec>,
+    #[serde(default)]
+    pub allow_extra_fields: bool,
+}
+
+impl ReadingSchema {
+    /// Parse and sanity-check a schema definition.
+    pub fn parse(schema_json: &str) -> Result<Self, LCoreError> {
+        let schema: ReadingSchema = serde_json::from_str(schema_json)?;
+        if schema.fields.is_empty() {
+            return Err(LCoreError::InvalidInput("Schema must declare at least one field".to_string()));
+        }
+        for (name, spec) in &schema.fields {
+            if let (Some(min), Some(max)) = (spec.min, spec.max) {
+                if min > max {
+                    return Err(LCoreError::InvalidInput(format!("Field {}: min is greater than max", name)));
+                }
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Validate a decoded reading, returning every violation found.
+    pub fn validate(&self, reading: &Value) -> Result<(), Vec<String>> {
+        let object = match reading.as_object() {
+            Some(object) => object,
+            None => return Err(vec!["reading must be a JSON object".to_string()]),
+        };
+
+        let mut errors = Vec::new();
+        for (name, spec) in &self.fields {
+            match object.get(name) {
+                None | Some(Value::Null) => {
+                    if spec.required {
+                        errors.push(format!("{}: missing required field", name));
+                    }
+                }
+                Some(value) => {
+                    if let Err(e) = spec.check(value) {
+                        errors.push(format!("{}: {}", name, e));
+                    }
+                }
+            }
+        }
+
+        if !self.allow_extra_fields {
+            let mut extra: Vec<&String> = object.keys().filter(|key| !self.fields.contains_key(*key)).collect();
+            extra.sort();
+            for key in extra {
+                errors.push(format!("{}: unexpected field", key));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl FieldSpec {
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let number = match (self.field_type, value) {
+            (FieldType::Number, Value::Number(n)) => n.as_f64(),
+            (FieldType::Integer, Value::Number(n)) if n.is_i64() || n.is_u64() => n.as_f64(),
+            (FieldType::String, Value::String(_)) | (FieldType::Boolean, Value::Bool(_)) => None,
+            _ => return Err(format!("expected {:?}", self.field_type).to_lowercase()),
+        };
+
+        if let Some(number) = number {
+            if let Some(min) = self.min {
+                if number < min {
+                    return Err(format!("{} is below minimum {}", number, min));
+                }
+            }
+            if let Some(max) = self.max {
+                if number > max {
+                    return Err(format!("{} is above maximum {}", number, max));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Validate raw reading bytes against a stored schema definition.
+pub fn validate_reading(schema_json: &str, data: &[u8]) -> Result<(), Vec<String>> {
+    let schema = ReadingSchema::parse(schema_json).map_err(|e| vec![format!("invalid schema: {}", e)])?;
+    let reading: Value = serde_json::from_slice(data).map_err(|e| vec![format!("reading is not JSON: {}", e)])?;
+    schema.validate(&reading)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TEMPERATURE: &str = r#"{"fields": {
+        "temperature": {"type": "number", "min": -273.15},
+        "humidity": {"type": "number", "required": false, "min": 0, "max": 100}
+    }}"#;
+
+    #[test]
+    fn test_valid_reading() {
+        let schema = ReadingSchema::parse(TEMPERATURE).unwrap();
+        assert!(schema.validate(&json!({"temperature": 4.5})).is_ok());
+        assert!(schema.validate(&json!({"temperature": 4.5, "humidity": 40})).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_readings() {
+        let schema = ReadingSchema::parse(TEMPERATURE).unwrap();
+
+        let errors = schema.validate(&json!({"humidity": 140, "pressure": 1013})).unwrap_err();
+        assert_eq!(errors, vec![
+            "humidity: 140 is above maximum 100".to_string(),
+            "temperature: missing required field".to_string(),
+            "pressure: unexpected field".to_string(),
+        ]);
+
+        let errors = schema.validate(&json!({"temperature": "hot"})).unwrap_err();
+        assert_eq!(errors, vec!["temperature: expected number".to_string()]);
+
+        assert!(validate_reading(TEMPERATURE, b"temperature:23.5").is_err());
+    }
+
+    #[test]
+    fn test_schema_definition_checks() {
+        assert!(ReadingSchema::parse(r#"{"fields": {}}"#).is_err());
+        assert!(ReadingSchema::parse(r#"{"fields": {"t": {"type": "number", "min": 5, "max": 1}}}"#).is_err());
+        assert!(ReadingSchema::parse(r#"{"fields": {"t": {"type": "decimal"}}}"#).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+// lcore-node/src/staking.rs
+//
+// Device stakes locked at registration and slashed for bad data
+
+use crate::access::MAX_STORED;
+use crate::database::Database;
+use crate::error::LCoreError;
+use crate::portals::Asset;
+use crate::rollup::{normalize_address, AdvanceMetadata};
+
+/// Stake a device owner locks from their deposited balance when registering.
+/// Slashed stake is credited to the `treasury` ledger balance. A deleted
+/// device's stake stays slashable for `unbonding_seconds` before its owner
+/// can release it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StakePolicy {
+    pub asset: Asset,
+    pub amount: u128,
+    pub treasury: String,
+    pub unbonding_seconds: u64,
+}
+
+impl StakePolicy {
+    pub fn new(asset: Asset, amount: u128, treasury: &str, unbonding_seconds: u64) -> Result<Self, LCoreError> {
+        if amount == 0 {
+            return Err(LCoreError::InvalidInput("Stake amount must be positive".to_string()));
+        }
+        Ok(Self { asset, amount, treasury: normalize_address(treasury)?, unbonding_seconds })
+    }
+
+    /// Move the stake from the owner's balance into the device's stake.
+    /// Returns false, changing nothing, when the owner has not deposited enough.
+    pub fn lock(&self, db: &Database, device_id: &str, owner: &str, metadata: &AdvanceMetadata) -> Result<bool, LCoreError> {
+        let tx = db.savepoint()?;
+        if !db.debit_balance(owner, &self.asset, self.amount)? {
+            return Ok(false);
+        }
+        db.insert_stake(device_id, owner, &self.asset, self.amount, metadata)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Move up to `amount` of a device's stake to the treasury; `None` takes
+    /// all of it. Returns the amount actually slashed.
+    pub fn slash(
+        &self,
+        db: &Database,
+        device_id: &str,
+        amount: Option<u128>,
+        reason: &str,
+        metadata: &AdvanceMetadata,
+    ) -> Result<u128, LCoreError> {
+        let stake = match db.get_stake(device_id)? {
+            Some(stake) => stake,
+            None => return Ok(0),
+        };
+        let slashed = amount.map_or(stake.amount, |amount| amount.min(stake.amount));
+        if slashed == 0 {
+            return Ok(0);
+        }
+
+        let tx = db.savepoint()?;
+        db.set_stake_amount(device_id, stake.amount - slashed)?;
+        db.credit_balance(&self.treasury, &stake.asset, slashed)?;
+        db.insert_slash(device_id, &stake.asset, slashed, reason, metadata)?;
+        tx.commit()?;
+        Ok(slashed)
+    }
+
+    /// Start the unbonding delay of a deleted device's stake. Returns when
+    /// the stake can be released, or `None` when the device has no stake.
+    pub fn unbond(&self, db: &Database, device_id: &str, metadata: &AdvanceMetadata) -> Result<Option<u64>, LCoreError> {
+        if db.get_stake(device_id)?.is_none() {
+            return Ok(None);
+        }
+        let until = metadata.timestamp.saturating_add(self.unbonding_seconds).min(MAX_STORED);
+        db.set_stake_unbonding(device_id, until)?;
+        Ok(Some(until))
+    }
+
+    /// Return what is left of an unbonded stake to its owner. Returns the
+    /// amount released, or why the stake cannot be released yet.
+    pub fn release(
+        &self,
+        db: &Database,
+        device_id: &str,
+        metadata: &AdvanceMetadata,
+    ) -> Result<Result<u128, String>, LCoreError> {
+        let stake = match db.get_stake(device_id)? {
+            Some(stake) => stake,
+            None => return Ok(Err(format!("device {} has no stake", device_id))),
+        };
+        match stake.unbonding_until {
+            None => return Ok(Err(format!("device {} must be deleted first", device_id))),
+            Some(until) if metadata.timestamp < until => {
+                return Ok(Err(format!("stake of device {} is unbonding until {}", device_id, until)));
+            }
+            Some(_) => {}
+        }
+        let tx = db.savepoint()?;
+        db.credit_balance(&stake.owner, &stake.asset, stake.amount)?;
+        db.delete_stake(device_id)?;
+        tx.commit()?;
+        Ok(Ok(stake.amount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+    const TREASURY: &str = "0x90f79bf6eb2c4f870365e785982e1f101e93b906";
+
+    #[test]
+    fn test_lock_slash_release() {
+        let db = Database::open(":memory:").unwrap();
+        let metadata = AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200);
+        let policy = StakePolicy::new(Asset::Ether, 100, TREASURY, 3_600).unwrap();
+
+        assert!(!policy.lock(&db, "did:example:staked", OWNER, &metadata).unwrap());
+        db.credit_balance(OWNER, &Asset::Ether, 150).unwrap();
+        assert!(policy.lock(&db, "did:example:staked", OWNER, &metadata).unwrap());
+        assert_eq!(db.get_balance(OWNER, &Asset::Ether).unwrap(), 50);
+
+        assert_eq!(policy.slash(&db, "did:example:staked", Some(30), "out of bounds", &metadata).unwrap(), 30);
+        assert_eq!(db.get_balance(TREASURY, &Asset::Ether).unwrap(), 30);
+        assert_eq!(db.get_stake("did:example:staked").unwrap().unwrap().amount, 70);
+
+        // Only a deleted device's stake is released, once it has unbonded
+        assert!(policy.release(&db, "did:example:staked", &metadata).unwrap().is_err());
+        assert_eq!(policy.unbond(&db, "did:example:staked", &metadata).unwrap(), Some(1_704_070_800));
+        assert!(policy.release(&db, "did:example:staked", &metadata).unwrap().is_err());
+        // Still slashable while unbonding
+        assert_eq!(policy.slash(&db, "did:example:staked", Some(10), "late evidence", &metadata).unwrap(), 10);
+
+        let later = AdvanceMetadata::for_test(OWNER, 2, 1_704_070_800);
+        assert_eq!(policy.release(&db, "did:example:staked", &later).unwrap(), Ok(60));
+        assert_eq!(db.get_balance(OWNER, &Asset::Ether).unwrap(), 110);
+        assert!(db.get_stake("did:example:staked").unwrap().is_none());
+        assert_eq!(policy.slash(&db, "did:example:staked", None, "gone", &metadata).unwrap(), 0);
+    }
+}
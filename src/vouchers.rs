@@ -0,0 +1,146 @@
+// lcore-node/src/vouchers.rs
+//
+// L1 calls emitted as vouchers, and the data-contribution reward policy
+
+use crate::abi::{self, Token};
+use crate::database::{Database, DeviceRow};
+use crate::error::LCoreError;
+use crate::portals::Asset;
+use crate::rollup::{normalize_address, AdvanceMetadata};
+use tracing::warn;
+
+/// `transfer(address,uint256)`
+pub const ERC20_TRANSFER_SELECTOR: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb];
+/// `withdrawEther(address,uint256)` on the dApp contract
+pub const WITHDRAW_ETHER_SELECTOR: [u8; 4] = [0x52, 0x2f, 0x68, 0x15];
+
+/// A call the dApp contract executes on L1 once the voucher is proven.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Voucher {
+    /// Contract the call is sent to, lowercased `0x` address
+    pub destination: String,
+    /// ABI-encoded calldata, selector included
+    pub payload: Vec<u8>,
+}
+
+impl Voucher {
+    /// Voucher calling `selector` on `destination` with `tokens` as arguments.
+    pub fn call(destination: &str, selector: [u8; 4], tokens: &[Token]) -> Result<Self, LCoreError> {
+        Ok(Self {
+            destination: normalize_address(destination)?,
+            payload: abi::encode_call(selector, tokens),
+        })
+    }
+
+    /// Voucher transferring `amount` of the ERC-20 `token` held by the dApp to `recipient`.
+    pub fn erc20_transfer(token: &str, recipient: &str, amount: u128) -> Result<Self, LCoreError> {
+        Self::call(token, ERC20_TRANSFER_SELECTOR, &[Token::address(recipient)?, Token::Uint(amount)])
+    }
+
+    /// Voucher making the dApp contract at `dapp_address` send `amount` wei to `recipient`.
+    pub fn ether_withdrawal(dapp_address: &str, recipient: &str, amount: u128) -> Result<Self, LCoreError> {
+        Self::call(dapp_address, WITHDRAW_ETHER_SELECTOR, &[Token::address(recipient)?, Token::Uint(amount)])
+    }
+}
+
+/// Pays device owners `amount` of `token` for every `every` authenticated
+/// readings their device contributes, out of the `pool` ledger balance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewardPolicy {
+    pub token: String,
+    pub amount: u128,
+    pub every: u64,
+    /// Ledger account rewards are paid from, funded by depositing `token` from it
+    pub pool: String,
+}
+
+impl RewardPolicy {
+    pub fn new(token: &str, amount: u128, every: u64, pool: &str) -> Result<Self, LCoreError> {
+        if amount == 0 || every == 0 {
+            return Err(LCoreError::InvalidInput("Reward amount and interval must be positive".to_string()));
+        }
+        Ok(Self { token: normalize_address(token)?, amount, every, pool: normalize_address(pool)? })
+    }
+
+    /// Count an accepted reading towards the device's reward and return the
+    /// payout voucher when the reading completes an interval. The payout is
+    /// debited from the pool; nothing is paid while the pool is short.
+    pub fn on_reading(
+        &self,
+        db: &Database,
+        device: &DeviceRow,
+        metadata: &AdvanceMetadata,
+    ) -> Result<Option<Voucher>, LCoreError> {
+        let readings = db.increment_reward_progress(&device.id)?;
+        if readings % self.every != 0 {
+            return Ok(None);
+        }
+
+        let tx = db.savepoint()?;
+        if !db.debit_balance(&self.pool, &Asset::Erc20(self.token.clone()), self.amount)? {
+            warn!("Reward pool {} cannot cover {} for device {}", self.pool, self.amount, device.id);
+            return Ok(None);
+        }
+        let voucher = Voucher::erc20_transfer(&self.token, &device.owner, self.amount)?;
+        db.insert_voucher(&voucher, &format!("reward:{}", device.id), metadata)?;
+        tx.commit()?;
+        Ok(Some(voucher))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+    const OWNER: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+    const POOL: &str = "0x90f79bf6eb2c4f870365e785982e1f101e93b906";
+
+    #[test]
+    fn test_erc20_transfer_voucher() {
+        let voucher = Voucher::erc20_transfer(TOKEN, OWNER, 5).unwrap();
+        assert_eq!(voucher.destination, TOKEN.to_lowercase());
+        assert_eq!(&voucher.payload[..4], &ERC20_TRANSFER_SELECTOR);
+        assert_eq!(&voucher.payload[16..36], hex::decode(&OWNER[2..]).unwrap().as_slice());
+        assert_eq!(voucher.payload[67], 5);
+        assert!(Voucher::erc20_transfer("0x1234", OWNER, 5).is_err());
+    }
+
+    #[test]
+    fn test_ether_withdrawal_voucher() {
+        let dapp = "0x243E9F2Ee6Af8f1c0783Ff10452CC1486820552c";
+        let voucher = Voucher::ether_withdrawal(dapp, OWNER, 1_000).unwrap();
+        assert_eq!(voucher.destination, dapp.to_lowercase());
+        assert_eq!(
+            hex::encode(&voucher.payload),
+            concat!(
+                "522f6815",
+                "000000000000000000000000f39fd6e51aad88f6f4ce6ab8827279cfffb92266",
+                "00000000000000000000000000000000000000000000000000000000000003e8",
+            )
+        );
+    }
+
+    #[test]
+    fn test_reward_every_n_readings() {
+        let db = Database::open(":memory:").unwrap();
+        let mut metadata = AdvanceMetadata::for_test(OWNER, 1, 1_704_067_200);
+        db.insert_device("did:example:meter", "{}", "{}", None, &[], &metadata).unwrap();
+        let device = db.get_device("did:example:meter").unwrap().unwrap();
+
+        let policy = RewardPolicy::new(TOKEN, 1_000, 3, POOL).unwrap();
+        let reward_token = Asset::Erc20(policy.token.clone());
+        db.credit_balance(POOL, &reward_token, 2_500).unwrap();
+        let mut payouts = Vec::new();
+        for input_index in 2..12 {
+            metadata.input_index = input_index;
+            if policy.on_reading(&db, &device, &metadata).unwrap().is_some() {
+                payouts.push(input_index);
+            }
+        }
+        // Readings 3 and 6 of the device complete an interval; the pool cannot cover reading 9
+        assert_eq!(payouts, vec![4, 7]);
+        assert_eq!(db.get_balance(POOL, &reward_token).unwrap(), 500);
+        assert!(RewardPolicy::new(TOKEN, 1_000, 0, POOL).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+// Drives `App` as a library, the way the rollup loop does
+
+use dapp::app::{App, Policies};
+use dapp::config::Config;
+use dapp::database::Database;
+use dapp::error::ErrorClass;
+use dapp::rollup_stub::{advance_request, DEFAULT_SENDER};
+use json::object;
+
+/// Nothing listens here; the inputs below produce no outputs to send
+const ROLLUP_SERVER: &str = "http://127.0.0.1:9";
+
+fn app() -> App {
+    let policies = Policies::from_config(&Config::default()).unwrap();
+    App::new(Database::open(":memory:").unwrap(), policies, ROLLUP_SERVER)
+}
+
+#[tokio::test]
+async fn test_register_then_submit() {
+    let app = app();
+
+    let register = serde_json::json!({
+        "action": "register",
+        "payload": {
+            "device_id": "did:example:library",
+            "did_document": "{}",
+            "public_key": "{}",
+        },
+    });
+    assert_eq!(app.handle(advance_request(DEFAULT_SENDER, 1, register.to_string().as_bytes())).await.unwrap(), "accept");
+    let device = app.db().get_device("did:example:library").unwrap().unwrap();
+    assert_eq!(device.owner, DEFAULT_SENDER);
+
+    let submit = serde_json::json!({
+        "action": "submit",
+        "payload": {
+            "device_id": "did:example:library",
+            "data": hex::encode(br#"{"temperature":21.5}"#),
+        },
+    });
+    assert_eq!(app.handle(advance_request(DEFAULT_SENDER, 2, submit.to_string().as_bytes())).await.unwrap(), "accept");
+    let reading = app.db().get_latest_sensor_data("did:example:library").unwrap().unwrap();
+    assert_eq!(reading.input_index, 2);
+
+    // `handle` would report these; `advance` hands back the classified error
+    let unknown = serde_json::json!({ "action": "teleport", "payload": {} });
+    let error = app.advance(advance_request(DEFAULT_SENDER, 3, unknown.to_string().as_bytes())).await.unwrap_err();
+    assert_eq!(error.class(), ErrorClass::Client);
+    let error = app.advance(object! { "request_type" => "advance_state" }).await.unwrap_err();
+    assert_eq!(error.class(), ErrorClass::Server);
+}
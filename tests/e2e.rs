@@ -0,0 +1,324 @@
+// Runs the real rollup loop against the in-process rollup server stand-in
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use dapp::app::{App, Policies};
+use dapp::batch;
+use dapp::config::Config;
+use dapp::database::Database;
+use dapp::device_auth;
+use dapp::replay;
+use dapp::rollup_stub::{advance_request, Output, RollupStub, DEFAULT_SENDER};
+use ed25519_dalek::{Keypair, Signer};
+use json::object;
+use serde_json::json;
+
+fn app(stub: &RollupStub) -> App {
+    let policies = Policies::from_config(&Config::default()).unwrap();
+    App::new(Database::open(":memory:").unwrap(), policies, &stub.url())
+}
+
+fn action(action: &str, payload: serde_json::Value) -> Vec<u8> {
+    json!({ "action": action, "payload": payload }).to_string().into_bytes()
+}
+
+/// Public JWK of `keypair`, and a compact JWS by it over `message`
+fn sign(keypair: &Keypair, message: &str) -> (String, String) {
+    let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"EdDSA"}"#);
+    let body = URL_SAFE_NO_PAD.encode(message);
+    let signature = keypair.sign(format!("{}.{}", header, body).as_bytes());
+    let jwk = json!({ "kty": "OKP", "crv": "Ed25519", "x": URL_SAFE_NO_PAD.encode(keypair.public.to_bytes()) });
+    (jwk.to_string(), format!("{}.{}.{}", header, body, URL_SAFE_NO_PAD.encode(signature.to_bytes())))
+}
+
+#[tokio::test]
+async fn test_submit_then_inspect() {
+    let stub = RollupStub::start().await.unwrap();
+    let app = app(&stub);
+
+    stub.advance(&action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": "{}" })));
+    stub.advance(&action(
+        "add_rule",
+        json!({ "device_id": "did:example:e2e", "field": "temperature", "comparator": "gt", "threshold": 25.0 }),
+    ));
+    stub.advance(&action("submit", json!({ "device_id": "did:example:e2e", "data": hex::encode(br#"{"temperature":30.5}"#) })));
+
+    // The owner proves its address with the reader key it registered, over the latest reading's counter
+    let reader = Keypair::generate(&mut rand::rngs::OsRng);
+    let challenge = device_auth::read_message("did:example:e2e", 1);
+    let (reader_key, jws) = sign(&reader, &challenge);
+    stub.advance(&action("set_reader_key", json!({ "public_key": reader_key })));
+    let query = format!("get_latest:did:example:e2e?consumer={}", DEFAULT_SENDER);
+    stub.inspect(query.as_bytes());
+    let (_, spoofed) = sign(&Keypair::generate(&mut rand::rngs::OsRng), &challenge);
+    stub.inspect(format!("{}&jws={}", query, spoofed).as_bytes());
+    stub.inspect(format!("{}&jws={}", query, jws).as_bytes());
+    // Alert history carries reading values and is guarded the same way
+    let alerts_query = format!("get_alerts:did:example:e2e?consumer={}", DEFAULT_SENDER);
+    stub.inspect(alerts_query.as_bytes());
+    stub.inspect(format!("{}&jws={}", alerts_query, jws).as_bytes());
+    stub.advance(&action("teleport", json!({})));
+
+    let processed = stub.run(&app).await.unwrap();
+    let statuses: Vec<&str> = processed.iter().map(|processed| processed.status.as_str()).collect();
+    assert_eq!(
+        statuses,
+        ["accept", "accept", "accept", "accept", "reject", "reject", "accept", "reject", "accept", "reject"]
+    );
+
+    let alerts = processed[2].notices();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0]["type"], "alert");
+    assert_eq!(alerts[0]["alert"]["state"], "triggered");
+    // The reading value is only in the guarded alert history
+    assert!(alerts[0]["alert"].get("value").is_none());
+
+    // Neither an unsigned query nor one signed by another key reads the owner's data
+    for denied in &processed[4..6] {
+        let reports = denied.reports();
+        assert_eq!(reports[0]["type"], "access_denied");
+        assert_eq!(reports[0]["challenge"], challenge.as_str());
+    }
+
+    let reports = processed[6].reports();
+    assert_eq!(reports[0]["type"], "decrypted_sensor_data");
+    let reading: serde_json::Value = serde_json::from_str(reports[0]["data"].as_str().unwrap()).unwrap();
+    assert_eq!(reading["decrypted_payload"], r#"{"temperature":30.5}"#);
+
+    assert_eq!(processed[7].reports()[0]["type"], "access_denied");
+    let reports = processed[8].reports();
+    assert_eq!(reports[0]["type"], "alerts");
+    assert_eq!(reports[0]["alerts"][0]["value"], 30.5);
+
+    let rejection = processed[9].reports();
+    assert_eq!(rejection[0]["type"], "error");
+    assert_eq!(rejection[0]["kind"], "invalid_input");
+}
+
+#[tokio::test]
+async fn test_protocol_error_raises_exception() {
+    let stub = RollupStub::start().await.unwrap();
+    let app = app(&stub);
+
+    stub.advance(&action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": "{}" })));
+    stub.push(object! { "request_type" => "advance_state", "data" => object! { "payload" => "0x" } });
+    stub.inspect(b"get_alerts:did:example:e2e");
+
+    // The loop stops at the exception; the inspect behind it is never handed out
+    let processed = stub.run(&app).await.unwrap();
+    assert_eq!(processed.len(), 2);
+    assert_eq!(processed[0].status, "accept");
+    assert_eq!(processed[1].status, "exception");
+    assert!(matches!(&processed[1].outputs[..], [Output::Exception(message)] if message.starts_with(b"Malformed rollup request")));
+}
+
+#[tokio::test]
+async fn test_oversized_access_terms_are_rejected() {
+    const ETHER_PORTAL: &str = "0xffdbe43d4c855bf7e0f105c400a50857f53ab044";
+    let stub = RollupStub::start().await.unwrap();
+    let mut config = Config::default();
+    config.portals.ether_portal = Some(ETHER_PORTAL.to_string());
+    let app = App::new(Database::open(":memory:").unwrap(), Policies::from_config(&config).unwrap(), &stub.url());
+
+    let mut deposit = hex::decode(DEFAULT_SENDER.trim_start_matches("0x")).unwrap();
+    deposit.extend_from_slice(&[0; 31]);
+    deposit.push(2);
+    stub.advance_from(ETHER_PORTAL, &deposit);
+    stub.advance(&action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": "{}" })));
+    let price = |period_seconds: u64| {
+        action(
+            "set_access_price",
+            json!({ "device_id": "did:example:e2e", "asset": "ether", "price": "1", "period_seconds": period_seconds }),
+        )
+    };
+    stub.advance(&price(u64::MAX));
+    stub.advance(&price(i64::MAX as u64));
+    // Two periods from now lie past what SQLite can store, so the expiry is clamped
+    stub.advance(&action("purchase_access", json!({ "device_id": "did:example:e2e", "quantity": 2 })));
+    stub.advance(&action("purchase_access", json!({ "device_id": "did:example:none", "quantity": 1 })));
+
+    let processed = stub.run(&app).await.unwrap();
+    let statuses: Vec<&str> = processed.iter().map(|processed| processed.status.as_str()).collect();
+    assert_eq!(statuses, ["accept", "accept", "reject", "accept", "accept", "reject"]);
+    assert_eq!(processed[2].reports()[0]["kind"], "policy");
+    assert_eq!(processed[4].reports()[0]["cost"], "2");
+    // A refused purchase is explained by a single error report
+    let refused = processed[5].reports();
+    assert_eq!(refused.len(), 1);
+    assert_eq!(refused[0]["kind"], "policy");
+    let grant = app.db().get_purchased_grant("did:example:e2e", DEFAULT_SENDER).unwrap().unwrap();
+    assert_eq!(grant.expires_at, Some(i64::MAX as u64));
+}
+
+#[tokio::test]
+async fn test_unregistered_and_duplicate_devices_are_refused() {
+    let stub = RollupStub::start().await.unwrap();
+    let app = app(&stub);
+
+    let register = action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": "{}" }));
+    stub.advance(&action("submit", json!({ "device_id": "did:example:e2e", "data": hex::encode(br#"{"temperature":30.5}"#) })));
+    stub.advance(&register);
+    stub.advance_from("0x70997970c51812dc3a010c7d01b50e0d17dc79c8", &register);
+
+    let processed = stub.run(&app).await.unwrap();
+    let statuses: Vec<&str> = processed.iter().map(|processed| processed.status.as_str()).collect();
+    assert_eq!(statuses, ["reject", "accept", "reject"]);
+    assert_eq!(processed[0].reports()[0]["reason"], "Device did:example:e2e is not registered");
+    assert_eq!(processed[2].reports()[0]["kind"], "policy");
+    assert_eq!(app.db().get_device("did:example:e2e").unwrap().unwrap().owner, DEFAULT_SENDER);
+    assert!(app.db().get_latest_sensor_data("did:example:e2e").unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_signed_readings_cannot_be_replayed() {
+    let stub = RollupStub::start().await.unwrap();
+    let app = app(&stub);
+
+    let device = Keypair::generate(&mut rand::rngs::OsRng);
+    let (public_key, _) = sign(&device, "");
+    stub.advance(&action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": public_key })));
+
+    let data = br#"{"temperature":21.5}"#;
+    let reading_jws = |counter: u64| {
+        let message = device_auth::reading_message("did:example:e2e", counter, data);
+        sign(&device, std::str::from_utf8(&message).unwrap()).1
+    };
+    let submit = action("submit", json!({ "device_id": "did:example:e2e", "jws": reading_jws(1), "data": hex::encode(data) }));
+    stub.advance(&submit);
+    stub.advance(&submit);
+
+    // A root over the next two counters is accepted once
+    let item = json!({ "device_id": "did:example:e2e", "data": hex::encode(data) });
+    let leaves: Vec<[u8; 32]> = (2..4).map(|counter| batch::reading_leaf("did:example:e2e", counter, data)).collect();
+    let root = batch::merkle_root(&leaves).unwrap();
+    let (_, root_jws) = sign(&device, &device_auth::batch_message("did:example:e2e", leaves.len(), &root));
+    let submit_batch = action("submit_batch", json!({ "items": [item, item], "root_jws": root_jws }));
+    stub.advance(&submit_batch);
+    stub.advance(&submit_batch);
+
+    let processed = stub.run(&app).await.unwrap();
+    let statuses: Vec<&str> = processed.iter().map(|processed| processed.status.as_str()).collect();
+    assert_eq!(statuses, ["accept", "accept", "reject", "accept", "reject"]);
+    assert!(processed[2].reports()[0]["reason"].as_str().unwrap().starts_with("Device authentication failed for counter 2"));
+    assert_eq!(processed[4].reports()[0]["accepted"], false);
+    assert_eq!(app.db().get_latest_sensor_data("did:example:e2e").unwrap().unwrap().counter, 3);
+}
+
+#[tokio::test]
+async fn test_overflowing_analytics_do_not_halt_the_node() {
+    let stub = RollupStub::start().await.unwrap();
+    let app = app(&stub);
+
+    stub.advance(&action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": "{}" })));
+    for data in [r#"{"x":1e308}"#, r#"{"x":-1e308}"#, r#"{"x":1}"#] {
+        stub.advance(&action("submit", json!({ "device_id": "did:example:e2e", "data": hex::encode(data) })));
+    }
+    stub.inspect(b"get_alerts:did:example:e2e");
+
+    let processed = stub.run(&app).await.unwrap();
+    let statuses: Vec<&str> = processed.iter().map(|processed| processed.status.as_str()).collect();
+    assert_eq!(statuses, ["accept", "accept", "accept", "accept", "reject"]);
+    assert_eq!(app.db().get_latest_sensor_data("did:example:e2e").unwrap().unwrap().counter, 3);
+}
+
+#[tokio::test]
+async fn test_unsigned_readings_do_not_slash() {
+    const ETHER_PORTAL: &str = "0xffdbe43d4c855bf7e0f105c400a50857f53ab044";
+    let stub = RollupStub::start().await.unwrap();
+    let mut config = Config::default();
+    config.portals.ether_portal = Some(ETHER_PORTAL.to_string());
+    config.auth.admin_addresses = vec![DEFAULT_SENDER.to_string()];
+    config.staking.amount = Some("10".to_string());
+    config.staking.treasury = Some("0x90f79bf6eb2c4f870365e785982e1f101e93b906".to_string());
+    let app = App::new(Database::open(":memory:").unwrap(), Policies::from_config(&config).unwrap(), &stub.url());
+
+    let mut deposit = hex::decode(DEFAULT_SENDER.trim_start_matches("0x")).unwrap();
+    deposit.extend_from_slice(&[0; 31]);
+    deposit.push(10);
+    stub.advance_from(ETHER_PORTAL, &deposit);
+    let device = Keypair::generate(&mut rand::rngs::OsRng);
+    let (public_key, _) = sign(&device, "");
+    stub.advance(&action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": public_key })));
+    stub.advance(&action(
+        "add_rule",
+        json!({ "device_id": "did:example:e2e", "field": "temperature", "comparator": "gt", "threshold": 25.0, "slash_amount": "4" }),
+    ));
+    let data = br#"{"temperature":30.5}"#;
+    // Anyone can submit an unsigned reading for the device
+    stub.advance_from(
+        "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+        &action("submit", json!({ "device_id": "did:example:e2e", "data": hex::encode(data) })),
+    );
+    stub.advance(&action("submit", json!({ "device_id": "did:example:e2e", "data": hex::encode(br#"{"temperature":20.0}"#) })));
+    let message = device_auth::reading_message("did:example:e2e", 3, data);
+    let (_, jws) = sign(&device, std::str::from_utf8(&message).unwrap());
+    stub.advance(&action("submit", json!({ "device_id": "did:example:e2e", "jws": jws, "data": hex::encode(data) })));
+
+    let processed = stub.run(&app).await.unwrap();
+    let statuses: Vec<&str> = processed.iter().map(|processed| processed.status.as_str()).collect();
+    assert_eq!(statuses, ["accept", "accept", "accept", "accept", "accept", "accept"]);
+    assert!(processed[3].notices().iter().all(|notice| notice["type"] != "slash"));
+    let slashes: Vec<_> = processed[5].notices().into_iter().filter(|notice| notice["type"] == "slash").collect();
+    assert_eq!(slashes.len(), 1);
+    assert_eq!(slashes[0]["amount"], "4");
+    assert_eq!(app.db().get_stake("did:example:e2e").unwrap().unwrap().amount, 6);
+}
+
+#[tokio::test]
+async fn test_owner_cannot_revoke_a_purchased_grant() {
+    const ETHER_PORTAL: &str = "0xffdbe43d4c855bf7e0f105c400a50857f53ab044";
+    const BUYER: &str = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+    let stub = RollupStub::start().await.unwrap();
+    let mut config = Config::default();
+    config.portals.ether_portal = Some(ETHER_PORTAL.to_string());
+    let app = App::new(Database::open(":memory:").unwrap(), Policies::from_config(&config).unwrap(), &stub.url());
+
+    let mut deposit = hex::decode(BUYER.trim_start_matches("0x")).unwrap();
+    deposit.extend_from_slice(&[0; 31]);
+    deposit.push(5);
+    stub.advance_from(ETHER_PORTAL, &deposit);
+    stub.advance(&action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": "{}" })));
+    stub.advance(&action(
+        "set_access_price",
+        json!({ "device_id": "did:example:e2e", "asset": "ether", "price": "1", "period_seconds": 3_600 }),
+    ));
+    stub.advance_from(BUYER, &action("purchase_access", json!({ "device_id": "did:example:e2e", "quantity": 2 })));
+    let access = |action_name: &str| action(action_name, json!({ "device_id": "did:example:e2e", "grantee": BUYER }));
+    stub.advance(&access("revoke_access"));
+    stub.advance(&access("grant_access"));
+    stub.advance(&access("revoke_access"));
+
+    let processed = stub.run(&app).await.unwrap();
+    let statuses: Vec<&str> = processed.iter().map(|processed| processed.status.as_str()).collect();
+    assert_eq!(statuses, ["accept", "accept", "accept", "accept", "reject", "accept", "accept"]);
+    assert_eq!(processed[4].reports()[0]["kind"], "policy");
+    let grant = app.db().get_purchased_grant("did:example:e2e", BUYER).unwrap().unwrap();
+    assert_eq!(grant.expires_at, Some(1_704_067_203 + 7_200));
+}
+
+/// Replay `log` from genesis and return the statuses and the final state hash
+async fn replay_state(log: &str) -> (Vec<String>, [u8; 32]) {
+    let stub = RollupStub::start().await.unwrap();
+    for request in replay::parse_log(log, None).unwrap() {
+        stub.push(request);
+    }
+    let app = app(&stub);
+    let processed = stub.run(&app).await.unwrap();
+    let statuses = processed.iter().map(|processed| processed.status.clone()).collect();
+    (statuses, app.db().state_hash().unwrap())
+}
+
+#[tokio::test]
+async fn test_rejected_input_leaves_no_state() {
+    let register = action("register", json!({ "device_id": "did:example:e2e", "did_document": "{}", "public_key": "{}" }));
+    let reading = action("submit", json!({ "device_id": "did:example:e2e", "data": hex::encode(br#"{"temperature":21.5}"#) }));
+    // The device counter is bumped before the out-of-range block time fails the insert
+    let mut rejected = advance_request(DEFAULT_SENDER, 1, &reading);
+    rejected["data"]["metadata"]["timestamp"] = u64::MAX.into();
+    let lines = [advance_request(DEFAULT_SENDER, 0, &register).dump(), rejected.dump()];
+
+    let (statuses, with_rejected) = replay_state(&lines.join("\n")).await;
+    assert_eq!(statuses, ["accept", "reject"]);
+    let (_, without) = replay_state(&lines[0]).await;
+    assert_eq!(with_rejected, without);
+}